@@ -0,0 +1,286 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+
+/// Compression formats, recognized by their extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    fn of(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" | "tgz" => Some(Self::Gzip),
+            "zst" | "zstd" | "tzst" => Some(Self::Zstd),
+            "xz" | "txz" => Some(Self::Xz),
+            _ => None,
+        }
+    }
+
+    fn decoder<'a, R: Read + 'a>(self, reader: R) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Self::Gzip => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+            #[cfg(feature = "xz")]
+            Self::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(reader)),
+            #[allow(unreachable_patterns)]
+            unsupported => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("{:?} support isn't built in", unsupported),
+                ))
+            }
+        })
+    }
+}
+
+/// Whether `path` is named like a compressed file (archives included).
+pub(crate) fn is_compressed(path: &Path) -> bool {
+    Compression::of(path).is_some()
+}
+
+/// The path a compressed file has once decompressed (`a.json.gz` is
+/// `a.json`), which tells its format. Other paths are kept as they are.
+pub(crate) fn inner_path(path: &Path) -> PathBuf {
+    match Compression::of(path) {
+        Some(_) => path.with_extension(""),
+        None => path.into(),
+    }
+}
+
+/// Opens a file, decompressing it on the fly when its extension says it's
+/// compressed.
+fn open(path: &Path) -> io::Result<Box<dyn Read>> {
+    let file = BufReader::new(File::open(path)?);
+    match Compression::of(path) {
+        Some(compression) => compression.decoder(file),
+        None => Ok(Box::new(file)),
+    }
+}
+
+/// Like `std::fs::read_to_string`, for compressed files too.
+pub(crate) fn read_to_string(path: &Path) -> io::Result<String> {
+    let mut contents = String::new();
+    open(path)?.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArchiveKind {
+    /// Compressed or not.
+    Tar,
+    Zip,
+}
+
+fn archive_kind(path: &Path) -> Option<ArchiveKind> {
+    match path.extension()?.to_str()? {
+        "zip" => Some(ArchiveKind::Zip),
+        "tar" | "tgz" | "tzst" | "txz" => Some(ArchiveKind::Tar),
+        _ if inner_path(path).extension()? == "tar" => Some(ArchiveKind::Tar),
+        _ => None,
+    }
+}
+
+/// Whether `path` is named like a tar or zip archive.
+pub(crate) fn is_archive(path: &Path) -> bool {
+    archive_kind(path).is_some()
+}
+
+/// Drops the `.` components, so `./a.yaml` and `a.yaml` name the same
+/// entry.
+fn entry_path(name: &Path) -> PathBuf {
+    name.components()
+        .filter(|component| component != &Component::CurDir)
+        .collect()
+}
+
+/// Calls `visit` with the path and contents of each file in the archive, in
+/// the order they're stored, until it returns `false`.
+fn visit_files<F>(path: &Path, mut visit: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(PathBuf, &mut dyn Read) -> Result<bool, Box<dyn Error>>,
+{
+    match archive_kind(path) {
+        Some(ArchiveKind::Tar) => {
+            let mut archive = tar::Archive::new(open(path)?);
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                if entry.header().entry_type().is_file() {
+                    let name = entry_path(&entry.path()?);
+                    if !visit(name, &mut entry)? {
+                        break;
+                    }
+                }
+            }
+        }
+        Some(ArchiveKind::Zip) => {
+            let mut archive = zip::ZipArchive::new(BufReader::new(File::open(path)?))?;
+            for index in 0..archive.len() {
+                let mut file = archive.by_index(index)?;
+                if file.is_file() {
+                    let name = entry_path(Path::new(file.name()));
+                    if !visit(name, &mut file)? {
+                        break;
+                    }
+                }
+            }
+        }
+        None => return Err(format!("{:?} isn't a tar or zip archive", path).into()),
+    }
+    Ok(())
+}
+
+/// Reads a file of an archive, decompressing it when its name says it's
+/// compressed.
+fn read_file(name: &Path, reader: &mut dyn Read) -> io::Result<String> {
+    let mut text = String::new();
+    match Compression::of(name) {
+        Some(compression) => compression.decoder(reader)?.read_to_string(&mut text)?,
+        None => reader.read_to_string(&mut text)?,
+    };
+    Ok(text)
+}
+
+/// Reads, in a single pass, the files of an archive for which `wanted`
+/// returns `true`, in the order they're stored.
+pub(crate) fn read_files<F>(
+    path: &Path,
+    mut wanted: F,
+) -> Result<Vec<(PathBuf, String)>, Box<dyn Error>>
+where
+    F: FnMut(&Path) -> bool,
+{
+    let mut found = Vec::new();
+    visit_files(path, |name, reader| {
+        if wanted(&name) {
+            let text = read_file(&name, reader)?;
+            found.push((name, text));
+        }
+        Ok(true)
+    })?;
+    Ok(found)
+}
+
+/// Reads the file at `entry` in an archive. Compressed files inside the
+/// archive are decompressed too.
+pub(crate) fn read_entry(path: &Path, entry: &Path) -> Result<String, Box<dyn Error>> {
+    let wanted = entry_path(entry);
+    let mut contents = None;
+    visit_files(path, |name, reader| {
+        if name != wanted {
+            return Ok(true);
+        }
+        contents = Some(read_file(&name, reader)?);
+        Ok(false)
+    })?;
+    contents.ok_or_else(|| format!("{:?} isn't in the archive", entry).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use test_case::test_case;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("mungye-archives-{}-{}", std::process::id(), name))
+    }
+
+    fn compress(name: &str, contents: &[u8]) -> Vec<u8> {
+        match Compression::of(Path::new(name)) {
+            Some(Compression::Gzip) => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(contents).unwrap();
+                encoder.finish().unwrap()
+            }
+            #[cfg(feature = "zstd")]
+            Some(Compression::Zstd) => zstd::stream::encode_all(contents, 0).unwrap(),
+            #[cfg(feature = "xz")]
+            Some(Compression::Xz) => {
+                let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+                encoder.write_all(contents).unwrap();
+                encoder.finish().unwrap()
+            }
+            #[allow(unreachable_patterns)]
+            Some(compression) => panic!("{:?} support isn't built in", compression),
+            None => contents.to_vec(),
+        }
+    }
+
+    #[test_case("a.json.gz", "a.json")]
+    #[cfg_attr(feature = "zstd", test_case("a.yaml.zst", "a.yaml"))]
+    #[cfg_attr(feature = "xz", test_case("a.xz", "a"))]
+    #[test_case("a.yaml", "a.yaml"; "not compressed")]
+    fn test_read_compressed(name: &str, inner: &str) {
+        let path = temp_path(name);
+        std::fs::write(&path, compress(name, b"a: 1\n")).unwrap();
+        let contents = read_to_string(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents.unwrap(), "a: 1\n");
+        assert_eq!(inner_path(Path::new(name)), Path::new(inner));
+    }
+
+    #[test_case("bundle.tar")]
+    #[test_case("bundle.tar.gz")]
+    #[test_case("bundle.tgz")]
+    #[cfg_attr(feature = "xz", test_case("bundle.tar.xz"))]
+    #[test_case("bundle.zip")]
+    fn test_archives(name: &str) {
+        let files: &[(&str, &[u8])] = &[
+            ("conf/b.yaml", b"b: 2\n"),
+            ("conf/a.json.gz", &compress("a.json.gz", b"{\"a\": 1}")),
+        ];
+        let path = temp_path(name);
+        let mut contents = Vec::new();
+        if archive_kind(&path) == Some(ArchiveKind::Zip) {
+            let mut writer = zip::ZipWriter::new(io::Cursor::new(&mut contents));
+            for (entry, data) in files {
+                writer
+                    .start_file(*entry, zip::write::SimpleFileOptions::default())
+                    .unwrap();
+                writer.write_all(data).unwrap();
+            }
+            writer.finish().unwrap();
+        } else {
+            let mut builder = tar::Builder::new(Vec::new());
+            for (entry, data) in files {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_mode(0o644);
+                header.set_cksum();
+                builder.append_data(&mut header, entry, *data).unwrap();
+            }
+            contents = compress(name, &builder.into_inner().unwrap());
+        }
+        std::fs::write(&path, contents).unwrap();
+
+        let all = read_files(&path, |_| true);
+        let some = read_files(&path, |name| name.ends_with("b.yaml"));
+        let b = read_entry(&path, Path::new("./conf/b.yaml"));
+        let a = read_entry(&path, Path::new("conf/a.json.gz"));
+        let missing = read_entry(&path, Path::new("conf/c.yaml"));
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(is_archive(&path));
+        assert_eq!(
+            all.unwrap(),
+            vec![
+                (PathBuf::from("conf/b.yaml"), "b: 2\n".to_string()),
+                (PathBuf::from("conf/a.json.gz"), "{\"a\": 1}".to_string())
+            ]
+        );
+        assert_eq!(
+            some.unwrap(),
+            vec![(PathBuf::from("conf/b.yaml"), "b: 2\n".to_string())]
+        );
+        assert_eq!(b.unwrap(), "b: 2\n");
+        assert_eq!(a.unwrap(), "{\"a\": 1}");
+        assert!(missing.is_err());
+    }
+}
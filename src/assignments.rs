@@ -0,0 +1,245 @@
+use json as jsonlib;
+use strum_macros::{Display, EnumString, EnumVariantNames};
+use yaml_rust as yamllib;
+
+use crate::conversions::{JsonValue, YamlValue};
+use crate::documents::Document;
+use crate::paths::ValuePath;
+
+/// A change made to the merged document from the command line, once every
+/// file has been folded.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Assignment {
+    Set(ValuePath, yamllib::Yaml),
+    Unset(ValuePath),
+}
+
+/// How raw values (like the ones of a `--set` expression) get their type.
+#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString, EnumVariantNames)]
+#[strum(serialize_all = "kebab-case")]
+pub(crate) enum Typing {
+    /// Like a plain YAML scalar: `1.5` is a float, `~` is null, and so on.
+    Yaml,
+    /// Like Helm's `--set`: only booleans, `null` and integers get a type,
+    /// everything else (floats included) stays a string.
+    Helm,
+    /// Everything is a string, like with `--set-string`.
+    String,
+}
+
+impl Typing {
+    pub fn value(self, raw: &str) -> yamllib::Yaml {
+        match self {
+            Self::Yaml => yamllib::Yaml::from_str(raw),
+            Self::Helm => helm_value(raw),
+            Self::String => yamllib::Yaml::String(raw.into()),
+        }
+    }
+}
+
+fn helm_value(raw: &str) -> yamllib::Yaml {
+    if raw.eq_ignore_ascii_case("true") {
+        yamllib::Yaml::Boolean(true)
+    } else if raw.eq_ignore_ascii_case("false") {
+        yamllib::Yaml::Boolean(false)
+    } else if raw.eq_ignore_ascii_case("null") {
+        yamllib::Yaml::Null
+    } else if raw == "0" {
+        yamllib::Yaml::Integer(0)
+    } else if !raw.starts_with('0') && !raw.starts_with("-0") {
+        raw.parse::<i64>()
+            .map(yamllib::Yaml::Integer)
+            .unwrap_or_else(|_| yamllib::Yaml::String(raw.into()))
+    } else {
+        yamllib::Yaml::String(raw.into())
+    }
+}
+
+/// Reads the key of an assignment, up to its `=`. Escapes are kept, paths
+/// know how to handle them.
+fn parse_key<I>(chars: &mut I, expression: &str) -> Result<ValuePath, String>
+where
+    I: Iterator<Item = char>,
+{
+    let mut key = String::new();
+    loop {
+        match chars.next() {
+            Some('=') => break,
+            Some('\\') => {
+                key.push('\\');
+                key.extend(chars.next());
+            }
+            Some(',') | None => {
+                return Err(format!("Key {:?} has no value in {:?}", key, expression));
+            }
+            Some(c) => key.push(c),
+        }
+    }
+    key.parse()
+}
+
+/// Parses a `--set` expression such as `a.b[0].c=x,d={1,2}` into a list of
+/// assignments. Commas separate assignments (`\,` is a literal comma) and a
+/// value wrapped in braces is a list.
+pub(crate) fn parse_set(expression: &str, typing: Typing) -> Result<Vec<Assignment>, String> {
+    let mut assignments = Vec::new();
+    let mut chars = expression.chars().peekable();
+
+    while chars.peek().is_some() {
+        let path = parse_key(&mut chars, expression)?;
+
+        let value = if chars.peek() == Some(&'{') {
+            chars.next();
+            let mut items = Vec::new();
+            let mut item = String::new();
+            loop {
+                match chars.next() {
+                    Some('}') => break,
+                    Some('\\') => item.extend(chars.next()),
+                    Some(',') => items.push(typing.value(&std::mem::take(&mut item))),
+                    Some(c) => item.push(c),
+                    None => return Err(format!("Unterminated list in {:?}", expression)),
+                }
+            }
+            if !item.is_empty() || !items.is_empty() {
+                items.push(typing.value(&item));
+            }
+            match chars.next() {
+                Some(',') | None => yamllib::Yaml::Array(items),
+                Some(c) => {
+                    return Err(format!("Unexpected {:?} after list in {:?}", c, expression))
+                }
+            }
+        } else {
+            let mut raw = String::new();
+            loop {
+                match chars.next() {
+                    Some(',') | None => break,
+                    Some('\\') => raw.extend(chars.next()),
+                    Some(c) => raw.push(c),
+                }
+            }
+            typing.value(&raw)
+        };
+
+        assignments.push(Assignment::Set(path, value));
+    }
+
+    Ok(assignments)
+}
+
+/// Parses a `--set-json` expression: a single `PATH=JSON` assignment.
+pub(crate) fn parse_set_json(expression: &str) -> Result<Assignment, String> {
+    let mut chars = expression.chars();
+    let path = parse_key(&mut chars, expression)?;
+    let value = jsonlib::parse(chars.as_str())
+        .map_err(|error| format!("Invalid JSON in {:?}: {}", expression, error))?;
+    Ok(Assignment::Set(path, YamlValue::from(JsonValue(value)).0))
+}
+
+/// Applies the assignments to the first document of the stream, in order.
+/// With `null_deletes` (like in Helm), assigning `null` deletes the key.
+pub(crate) fn apply(
+    document: Document,
+    assignments: &[Assignment],
+    null_deletes: bool,
+) -> Document {
+    let doc_type = document.doc_type();
+    let mut yaml = document.into_yaml();
+    if yaml.is_empty() {
+        yaml.push(YamlValue::default().0);
+    }
+
+    for assignment in assignments {
+        match assignment {
+            Assignment::Set(path, yamllib::Yaml::Null) if null_deletes => {
+                path.remove(&mut yaml[0]);
+            }
+            Assignment::Set(path, value) => path.set(&mut yaml[0], value.clone()),
+            Assignment::Unset(path) => {
+                path.remove(&mut yaml[0]);
+            }
+        }
+    }
+
+    Document::from_yaml(doc_type, yaml)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn load(source: &str) -> yamllib::Yaml {
+        yamllib::YamlLoader::load_from_str(source)
+            .unwrap()
+            .remove(0)
+    }
+
+    fn applied(assignments: &[Assignment], null_deletes: bool) -> yamllib::Yaml {
+        apply(Document::Yaml(vec![]), assignments, null_deletes)
+            .into_yaml()
+            .remove(0)
+    }
+
+    #[test_case("a=1", "{a: 1}")]
+    #[test_case("a.b=x,c=true", "{a: {b: x}, c: true}")]
+    #[test_case("a.b[1].c=1.5", "{a: {b: [~, {c: '1.5'}]}}"; "floats stay strings")]
+    #[test_case("a=007", "{a: '007'}"; "leading zeros stay strings")]
+    #[test_case(r"a=x\,y", "{a: 'x,y'}"; "escaped comma")]
+    #[test_case(r"a\.b=1", "{'a.b': 1}"; "escaped dot")]
+    #[test_case("a={1,two},b=", "{a: [1, two], b: ''}"; "lists")]
+    #[test_case("a=1,a=null", "{}"; "null deletes")]
+    fn test_parse_helm_set(expression: &str, expected: &str) {
+        let assignments = parse_set(expression, Typing::Helm).unwrap();
+        assert_eq!(applied(&assignments, true), load(expected));
+    }
+
+    #[test_case("a=1.5,b=0x10,c=~,d=yes", "{a: 1.5, b: 16, c: ~, d: 'yes'}")]
+    #[test_case("items[2].name=web", "{items: [~, ~, {name: web}]}")]
+    #[test_case("a={1,two,false}", "{a: [1, two, false]}"; "lists")]
+    fn test_parse_yaml_set(expression: &str, expected: &str) {
+        let assignments = parse_set(expression, Typing::Yaml).unwrap();
+        assert_eq!(applied(&assignments, false), load(expected));
+    }
+
+    #[test]
+    fn test_parse_string_set() {
+        let assignments = parse_set("a=1,b=true,c={~}", Typing::String).unwrap();
+        assert_eq!(
+            applied(&assignments, false),
+            load("{a: '1', b: 'true', c: ['~']}")
+        );
+    }
+
+    #[test_case(r#"tags=["a", true]"#, "{tags: [a, true]}")]
+    #[test_case(r#"a.b={"c": {"d": null}}"#, "{a: {b: {c: {d: ~}}}}"; "objects")]
+    fn test_parse_set_json(expression: &str, expected: &str) {
+        let assignment = parse_set_json(expression).unwrap();
+        assert_eq!(applied(&[assignment], false), load(expected));
+    }
+
+    #[test_case("a=x=y"; "invalid json")]
+    #[test_case("a"; "no value")]
+    fn test_parse_invalid_set_json(expression: &str) {
+        assert!(parse_set_json(expression).is_err());
+    }
+
+    #[test]
+    fn test_apply_in_order() {
+        let assignments = [
+            Assignment::Set("a.b".parse().unwrap(), yamllib::Yaml::Integer(1)),
+            Assignment::Set("a.c".parse().unwrap(), yamllib::Yaml::Null),
+            Assignment::Unset("a.b".parse().unwrap()),
+        ];
+        assert_eq!(applied(&assignments, false), load("{a: {c: ~}}"));
+        assert_eq!(applied(&assignments, true), load("{a: {}}"));
+    }
+
+    #[test_case("a")]
+    #[test_case("a,b=1")]
+    #[test_case("a={1,2")]
+    fn test_parse_invalid_set(expression: &str) {
+        assert!(parse_set(expression, Typing::Yaml).is_err());
+    }
+}
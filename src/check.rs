@@ -0,0 +1,86 @@
+use std::fs::read_to_string;
+use std::io;
+use std::path::Path;
+
+use strum_macros::{Display, EnumString, EnumVariantNames};
+
+use crate::commands::render_changes;
+use crate::diff::diff;
+use crate::documents::{DocumentError, DocumentType};
+
+/// How `--check` decides whether a generated file is up to date.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Display, EnumString, EnumVariantNames)]
+#[strum(serialize_all = "kebab-case")]
+pub(crate) enum CheckMode {
+    /// The file has to hold the same values, however they're written.
+    #[default]
+    Semantic,
+    /// The file has to be exactly what would be written.
+    Bytes,
+}
+
+/// Compares `rendered` (a `doc_type` document) with the contents of
+/// `filename`. Returns what's different, or `None` when the file is up to
+/// date.
+pub(crate) fn stale(
+    filename: &Path,
+    rendered: &str,
+    doc_type: DocumentType,
+    mode: CheckMode,
+) -> Result<Option<String>, DocumentError> {
+    let existing = match read_to_string(filename) {
+        Ok(existing) => existing,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            return Ok(Some(format!("{:?} doesn't exist\n", filename)))
+        }
+        Err(error) => {
+            return Err(DocumentError::Loading {
+                filename: filename.into(),
+                error: Box::new(error),
+            })
+        }
+    };
+
+    if mode == CheckMode::Bytes && existing == rendered {
+        return Ok(None);
+    }
+
+    let existing = match doc_type.load_from_str(&existing, filename) {
+        Ok(existing) => existing.into_yaml(),
+        Err(error) => return Ok(Some(format!("{}\n", error))),
+    };
+    let expected = doc_type.load_from_str(rendered, filename)?.into_yaml();
+
+    let changes = diff(&existing, &expected);
+    if !changes.is_empty() {
+        Ok(Some(render_changes(
+            &changes,
+            existing.len() > 1 || expected.len() > 1,
+        )))
+    } else if mode == CheckMode::Bytes {
+        Ok(Some(format!("{:?} differs only in formatting\n", filename)))
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::write;
+    use test_case::test_case;
+
+    #[test_case("same", "{\"a\": 1}\n", CheckMode::Semantic, false)]
+    #[test_case("formatting", "{\"a\":1}", CheckMode::Semantic, false)]
+    #[test_case("formatting-bytes", "{\"a\":1}", CheckMode::Bytes, true)]
+    #[test_case("same-bytes", "{\"a\": 1}\n", CheckMode::Bytes, false)]
+    #[test_case("changed", "{\"a\": 2}\n", CheckMode::Semantic, true)]
+    fn test_stale(name: &str, existing: &str, mode: CheckMode, expected: bool) {
+        let filename =
+            std::env::temp_dir().join(format!("mungye-check-{}-{}.json", std::process::id(), name));
+        write(&filename, existing).unwrap();
+        let result = stale(&filename, "{\"a\": 1}\n", DocumentType::Json, mode).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+        assert_eq!(result.is_some(), expected);
+    }
+}
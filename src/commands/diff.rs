@@ -0,0 +1,73 @@
+use std::io::{self, Write};
+
+use json as jsonlib;
+use structopt::StructOpt;
+use strum::VariantNames;
+
+use crate::commands::ReportFormat;
+use crate::diff::{diff, Change};
+use crate::inputs::InputSpec;
+
+#[derive(StructOpt, Debug)]
+pub(crate) struct DiffArgs {
+    /// The original document. Like the inputs of the main command, it can
+    /// be another source, like `git:HEAD:values.yaml`.
+    #[structopt(name = "OLD")]
+    old: InputSpec,
+
+    /// The document to compare it with, in any supported format.
+    #[structopt(name = "NEW")]
+    new: InputSpec,
+
+    /// `text` prints one line per change (`+` added, `-` removed and
+    /// `~` changed), `json` prints an array of changes.
+    #[structopt(long = "format", default_value, possible_values = &ReportFormat::VARIANTS)]
+    format: ReportFormat,
+}
+
+/// Renders changes as lines of text. When the streams hold more than one
+/// document, changes are grouped under a header per document.
+pub(crate) fn render_changes(changes: &[Change], multiple_documents: bool) -> String {
+    let mut rendered = String::new();
+    let mut current_document = None;
+    for change in changes {
+        if multiple_documents && current_document != Some(change.document()) {
+            current_document = Some(change.document());
+            rendered.push_str(&format!("@ document {}\n", change.document()));
+        }
+        rendered.push_str(&format!("{}\n", change));
+    }
+    rendered
+}
+
+impl DiffArgs {
+    pub fn run(self) -> i32 {
+        let (old, new) = match (self.old.load(), self.new.load()) {
+            (Ok((old, _)), Ok((new, _))) => (old.into_yaml(), new.into_yaml()),
+            (Err(error), _) | (_, Err(error)) => {
+                eprintln!("{}", error);
+                return 2;
+            }
+        };
+
+        let changes = diff(&old, &new);
+        let rendered = match self.format {
+            ReportFormat::Text => render_changes(&changes, old.len() > 1 || new.len() > 1),
+            ReportFormat::Json => format!(
+                "{}\n",
+                jsonlib::JsonValue::Array(changes.iter().map(Change::to_json).collect()).pretty(2)
+            ),
+        };
+
+        if let Err(error) = write!(io::stdout().lock(), "{}", rendered) {
+            eprintln!("Error writing to stdout: {}", error);
+            return 2;
+        }
+
+        if changes.is_empty() {
+            0
+        } else {
+            1
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::HashSet;
+use std::fs::create_dir_all;
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+use strum::VariantNames;
+
+use crate::documents::{write_atomically, Document, DocumentType};
+use crate::merging::{factor, unmerge, ArrayMergeBehavior};
+
+#[derive(StructOpt, Debug)]
+pub(crate) struct FactorArgs {
+    /// The sibling documents to factor.
+    #[structopt(name = "FILE", parse(from_os_str), required = true, min_values = 2)]
+    filenames: Vec<PathBuf>,
+
+    /// Directory where the base and the rewritten inputs are written.
+    #[structopt(long = "out-dir", parse(from_os_str))]
+    out_dir: PathBuf,
+
+    /// Name (without extension) of the file holding the common base.
+    #[structopt(long = "base-name", default_value = "base")]
+    base_name: String,
+
+    /// How arrays will be merged, see the main command.
+    #[structopt(long = "arrays", default_value, possible_values = &ArrayMergeBehavior::VARIANTS)]
+    array_merge: ArrayMergeBehavior,
+
+    /// Force every output to be in a specific format, otherwise each overlay
+    /// keeps the format of its input and the base the one of the first input.
+    #[structopt(long = "force-format", possible_values = &DocumentType::VARIANTS)]
+    force_format: Option<DocumentType>,
+}
+
+impl FactorArgs {
+    pub fn run(self) -> i32 {
+        let documents = match self
+            .filenames
+            .iter()
+            .map(Document::load)
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(documents) => documents,
+            Err(error) => {
+                eprintln!("{}", error);
+                return 2;
+            }
+        };
+
+        let doc_types: Vec<DocumentType> = documents
+            .iter()
+            .map(|document| self.force_format.unwrap_or_else(|| document.doc_type()))
+            .collect();
+        let streams: Vec<_> = documents.into_iter().map(Document::into_yaml).collect();
+        let base = factor(&streams, self.array_merge);
+
+        let mut outputs = vec![(
+            self.out_dir
+                .join(&self.base_name)
+                .with_extension(doc_types[0].to_string()),
+            Document::from_yaml(doc_types[0], base.clone()),
+        )];
+
+        for ((filename, stream), doc_type) in self.filenames.iter().zip(&streams).zip(doc_types) {
+            let overlay = match unmerge(&base, stream, self.array_merge, false) {
+                Ok(overlay) => overlay,
+                Err(problems) => {
+                    // Shouldn't happen, the base only holds what every input has
+                    for problem in problems {
+                        eprintln!(
+                            "Can't reproduce {} in {:?}: {}",
+                            problem.path.describe(),
+                            filename,
+                            problem.reason
+                        );
+                    }
+                    return 1;
+                }
+            };
+            let name = match filename.file_name() {
+                Some(name) => self.out_dir.join(name).with_extension(doc_type.to_string()),
+                None => {
+                    eprintln!("Can't name the overlay for {:?}", filename);
+                    return 2;
+                }
+            };
+            outputs.push((name, Document::from_yaml(doc_type, overlay)));
+        }
+
+        let mut seen = HashSet::new();
+        if let Some((duplicated, _)) = outputs.iter().find(|(name, _)| !seen.insert(name)) {
+            eprintln!(
+                "More than one output would be written to {:?} (see `--base-name`)",
+                duplicated
+            );
+            return 2;
+        }
+
+        if let Err(error) = create_dir_all(&self.out_dir) {
+            eprintln!("Error creating {:?}: {}", self.out_dir, error);
+            return 2;
+        }
+
+        // Everything is rendered before writing, so nothing is written when
+        // a piece can't be
+        let rendered = outputs
+            .into_iter()
+            .map(|(name, document)| {
+                document
+                    .render()
+                    .map(|rendered| (name.clone(), rendered))
+                    .map_err(|error| format!("Can't write {:?}: {}", name, error))
+            })
+            .collect::<Result<Vec<_>, _>>();
+        let rendered = match rendered {
+            Ok(rendered) => rendered,
+            Err(error) => {
+                eprintln!("{}", error);
+                return 2;
+            }
+        };
+
+        for (name, rendered) in rendered {
+            if let Err(error) = write_atomically(&name, &rendered) {
+                eprintln!("Error writing {:?}: {}", name, error);
+                return 2;
+            }
+        }
+        0
+    }
+}
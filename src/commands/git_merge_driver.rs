@@ -0,0 +1,111 @@
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use structopt::StructOpt;
+use strum::VariantNames;
+
+use crate::commands::merge3::{report_conflicts, MarkerLabels};
+use crate::documents::{write_atomically, Document, DocumentError, DocumentType};
+use crate::merging::{merge3, ConflictStyle};
+
+#[derive(StructOpt, Debug)]
+pub(crate) struct GitMergeDriverArgs {
+    /// The common ancestor's version (`%O`).
+    #[structopt(name = "ANCESTOR", parse(from_os_str))]
+    ancestor: PathBuf,
+
+    /// The current version (`%A`), the result is written back into it.
+    #[structopt(name = "CURRENT", parse(from_os_str))]
+    current: PathBuf,
+
+    /// The other branch's version (`%B`).
+    #[structopt(name = "OTHER", parse(from_os_str))]
+    other: PathBuf,
+
+    /// The path of the file being merged (`%P`), used to infer its format.
+    #[structopt(name = "PATHNAME", parse(from_os_str))]
+    pathname: PathBuf,
+
+    /// How to handle conflicts, see `merge3`. Git expects conflicts to be
+    /// left in the file, so this defaults to `markers`.
+    #[structopt(long = "conflict-style", default_value = "markers", possible_values = &ConflictStyle::VARIANTS)]
+    conflict_style: ConflictStyle,
+
+    #[structopt(flatten)]
+    markers: MarkerLabels,
+}
+
+/// Loads one of the versions git hands us. An empty file (like the ancestor
+/// when both sides added the same file) is an empty stream.
+fn load_version(doc_type: DocumentType, filename: &Path) -> Result<Document, DocumentError> {
+    let content = read_to_string(filename).map_err(|error| DocumentError::Loading {
+        filename: filename.into(),
+        error: Box::new(error),
+    })?;
+
+    if content.trim().is_empty() {
+        Ok(Document::from_yaml(doc_type, Vec::new()))
+    } else {
+        doc_type.load_from_str(content, filename)
+    }
+}
+
+impl GitMergeDriverArgs {
+    pub fn run(self) -> i32 {
+        let doc_type = match DocumentType::from_path(&self.pathname) {
+            Ok(doc_type) => doc_type,
+            Err(_) => {
+                eprintln!("Can't infer the format of {:?}", self.pathname);
+                return 2;
+            }
+        };
+
+        let loaded = [&self.ancestor, &self.current, &self.other]
+            .iter()
+            .map(|filename| load_version(doc_type, filename))
+            .collect::<Result<Vec<_>, _>>();
+
+        let (ancestor, current, other) = match loaded {
+            Ok(mut loaded) => {
+                let other = loaded.remove(2);
+                let current = loaded.remove(1);
+                (loaded.remove(0), current, other)
+            }
+            Err(error) => {
+                eprintln!("{}", error);
+                return 2;
+            }
+        };
+
+        let (merged, conflicts) = merge3(
+            ancestor.into_yaml(),
+            current.into_yaml(),
+            other.into_yaml(),
+            self.conflict_style,
+            &self.markers.into(),
+        );
+
+        if !conflicts.is_empty() {
+            eprintln!("Conflicts merging {:?}:", self.pathname);
+            report_conflicts(&conflicts);
+        }
+
+        let rendered = match Document::from_yaml(doc_type, merged).render() {
+            Ok(rendered) => rendered,
+            Err(error) => {
+                eprintln!("Error merging {:?}: {}", self.pathname, error);
+                return 2;
+            }
+        };
+        if let Err(error) = write_atomically(&self.current, &rendered) {
+            eprintln!("Error writing {:?}: {}", self.current, error);
+            return 2;
+        }
+
+        if conflicts.is_empty() {
+            0
+        } else {
+            1
+        }
+    }
+}
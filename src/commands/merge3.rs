@@ -0,0 +1,127 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+use strum::VariantNames;
+
+use crate::conversions::inline_value;
+use crate::documents::{Document, DocumentType};
+use crate::merging::{merge3, Conflict, ConflictMarkers, ConflictStyle};
+
+#[derive(StructOpt, Debug)]
+pub(crate) struct Merge3Args {
+    /// The common ancestor of both sides.
+    #[structopt(name = "BASE", parse(from_os_str))]
+    base: PathBuf,
+
+    /// Our side. Its format is used for the output.
+    #[structopt(name = "OURS", parse(from_os_str))]
+    ours: PathBuf,
+
+    /// Their side.
+    #[structopt(name = "THEIRS", parse(from_os_str))]
+    theirs: PathBuf,
+
+    /// How to handle conflicts: `report` keeps our side and lists the
+    /// conflicting paths in stderr, `markers` embeds every side in the output
+    /// under conflict marker keys (like `<<<<<<< ours`).
+    #[structopt(long = "conflict-style", default_value, possible_values = &ConflictStyle::VARIANTS)]
+    conflict_style: ConflictStyle,
+
+    #[structopt(flatten)]
+    markers: MarkerLabels,
+
+    /// Force output to be in a specific format, otherwise the format of
+    /// `OURS` is used.
+    #[structopt(long = "force-format", possible_values = &DocumentType::VARIANTS)]
+    force_format: Option<DocumentType>,
+}
+
+/// Labels for the conflict marker keys.
+#[derive(StructOpt, Debug)]
+pub(crate) struct MarkerLabels {
+    /// Label for our side in conflict markers.
+    #[structopt(long = "ours-label", default_value = "ours")]
+    ours: String,
+
+    /// Label for the common ancestor in conflict markers.
+    #[structopt(long = "base-label", default_value = "base")]
+    base: String,
+
+    /// Label for their side in conflict markers.
+    #[structopt(long = "theirs-label", default_value = "theirs")]
+    theirs: String,
+}
+
+impl From<MarkerLabels> for ConflictMarkers {
+    fn from(MarkerLabels { ours, base, theirs }: MarkerLabels) -> Self {
+        Self { ours, base, theirs }
+    }
+}
+
+pub(crate) fn report_conflicts(conflicts: &[Conflict]) {
+    let side = |value: &Option<yaml_rust::Yaml>| match value {
+        Some(value) => inline_value(value),
+        None => "(absent)".into(),
+    };
+
+    for conflict in conflicts {
+        eprintln!(
+            "Conflict at {}: ours {}, theirs {} (base {})",
+            conflict.path.describe(),
+            side(&conflict.ours),
+            side(&conflict.theirs),
+            side(&conflict.base),
+        );
+    }
+}
+
+impl Merge3Args {
+    pub fn run(self) -> i32 {
+        let loaded = [&self.base, &self.ours, &self.theirs]
+            .iter()
+            .map(Document::load)
+            .collect::<Result<Vec<_>, _>>();
+
+        let (base, ours, theirs) = match loaded {
+            Ok(mut loaded) => {
+                let theirs = loaded.remove(2);
+                let ours = loaded.remove(1);
+                (loaded.remove(0), ours, theirs)
+            }
+            Err(error) => {
+                eprintln!("{}", error);
+                return 1;
+            }
+        };
+
+        let doc_type = self.force_format.unwrap_or_else(|| ours.doc_type());
+        let (merged, conflicts) = merge3(
+            base.into_yaml(),
+            ours.into_yaml(),
+            theirs.into_yaml(),
+            self.conflict_style,
+            &self.markers.into(),
+        );
+
+        report_conflicts(&conflicts);
+
+        let rendered = match Document::from_yaml(doc_type, merged).render() {
+            Ok(rendered) => rendered,
+            Err(error) => {
+                eprintln!("{}", error);
+                return 1;
+            }
+        };
+        if let Err(error) = write!(io::stdout().lock(), "{}", rendered) {
+            eprintln!("Error writing to stdout: {}", error);
+            return 1;
+        }
+
+        if conflicts.is_empty() {
+            0
+        } else {
+            1
+        }
+    }
+}
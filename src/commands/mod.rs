@@ -0,0 +1,67 @@
+mod diff;
+mod factor;
+mod git_merge_driver;
+mod merge3;
+mod split;
+mod unmerge;
+
+pub(crate) use self::diff::render_changes;
+
+use structopt::StructOpt;
+use strum_macros::{Display, EnumString, EnumVariantNames};
+
+/// Operations other than the default merge of the `FILE` arguments.
+#[derive(StructOpt, Debug)]
+pub(crate) enum Command {
+    /// Three-way merge: combine the changes `OURS` and `THEIRS` made to
+    /// their common ancestor `BASE`. Exits with 1 when conflicts remain.
+    Merge3(merge3::Merge3Args),
+
+    /// Git merge driver, meant to be configured as
+    /// `mungye git-merge-driver %O %A %B %P`. The merge result is written
+    /// into `%A` and the exit code is 1 when conflicts remain.
+    GitMergeDriver(git_merge_driver::GitMergeDriverArgs),
+
+    /// Compare two documents semantically, in any combination of formats.
+    /// Key order and the way numbers are written are ignored. Exits with 1
+    /// when the documents differ.
+    Diff(diff::DiffArgs),
+
+    /// Compute the smallest overlay that, merged onto `BASE`, reproduces
+    /// `TARGET`. Fails when `TARGET` can't be reached by merging (like when
+    /// it lacks keys `BASE` has).
+    Unmerge(unmerge::UnmergeArgs),
+
+    /// Extract the largest subtree shared by every `FILE` into a base file,
+    /// and rewrite each input as an overlay that reproduces it when merged
+    /// onto the base.
+    Factor(factor::FactorArgs),
+
+    /// Write each key of a mapping (the whole document, or the one at
+    /// `--path`) into its own file named after the key, or each document of
+    /// a multi-document stream into its own numbered file.
+    Split(split::SplitArgs),
+}
+
+/// How reports meant for humans or for other programs are printed.
+#[derive(Debug, Clone, Copy, Default, Display, EnumString, EnumVariantNames)]
+#[strum(serialize_all = "kebab-case")]
+pub(crate) enum ReportFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl Command {
+    /// Runs the command, returning the process' exit code.
+    pub fn run(self) -> i32 {
+        match self {
+            Self::Merge3(args) => args.run(),
+            Self::GitMergeDriver(args) => args.run(),
+            Self::Diff(args) => args.run(),
+            Self::Unmerge(args) => args.run(),
+            Self::Factor(args) => args.run(),
+            Self::Split(args) => args.run(),
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::HashSet;
+use std::fs::create_dir_all;
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+use strum::VariantNames;
+use strum_macros::{Display, EnumString, EnumVariantNames};
+use yaml_rust as yamllib;
+
+use crate::documents::{write_atomically, Document, DocumentType};
+use crate::paths::{key_string, ValuePath};
+
+/// What each of the written files holds.
+#[derive(Debug, Clone, Copy, Default, Display, EnumString, EnumVariantNames)]
+#[strum(serialize_all = "kebab-case")]
+pub(crate) enum SplitBy {
+    /// The value of each key of the mapping, in a file named after the key.
+    #[default]
+    TopLevelKey,
+    /// Each document of a multi-document stream, numbered from 0.
+    Document,
+}
+
+#[derive(StructOpt, Debug)]
+pub(crate) struct SplitArgs {
+    /// The document to split.
+    #[structopt(name = "FILE", parse(from_os_str))]
+    filename: PathBuf,
+
+    /// What to split the document into.
+    #[structopt(long = "by", default_value, possible_values = &SplitBy::VARIANTS)]
+    by: SplitBy,
+
+    /// Split the mapping at this path (like `a.b[0].c`) instead of the
+    /// whole document. Can't be combined with `--by document`.
+    #[structopt(long = "path")]
+    path: Option<ValuePath>,
+
+    /// Directory where the pieces are written.
+    #[structopt(long = "out-dir", parse(from_os_str))]
+    out_dir: PathBuf,
+
+    /// Force every output to be in a specific format, otherwise the format
+    /// of `FILE` is used.
+    #[structopt(long = "force-format", possible_values = &DocumentType::VARIANTS)]
+    force_format: Option<DocumentType>,
+}
+
+/// Whether `name` can be used as a file name inside the output directory,
+/// without escaping it.
+fn safe_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(&['/', '\\'][..])
+}
+
+impl SplitArgs {
+    /// The pieces `stream` splits into, paired with their file names
+    /// (without extension).
+    fn pieces(&self, stream: Vec<yamllib::Yaml>) -> Result<Vec<(String, yamllib::Yaml)>, String> {
+        match self.by {
+            SplitBy::Document => {
+                if self.path.is_some() {
+                    return Err("`--path` can only be used with `--by top-level-key`".into());
+                }
+                let stem = self
+                    .filename
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .ok_or_else(|| format!("Can't name the pieces of {:?}", self.filename))?;
+                Ok(stream
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, document)| (format!("{}-{}", stem, index), document))
+                    .collect())
+            }
+            SplitBy::TopLevelKey => {
+                if stream.len() > 1 {
+                    return Err(format!(
+                        "{:?} holds {} documents, use `--by document` to split them",
+                        self.filename,
+                        stream.len()
+                    ));
+                }
+                let path = self.path.clone().unwrap_or_default();
+                let hash = match stream.first().and_then(|document| path.get(document)) {
+                    Some(yamllib::Yaml::Hash(hash)) => hash,
+                    Some(_) => return Err(format!("{} isn't a mapping", path.describe())),
+                    None => return Err(format!("There's nothing at {}", path.describe())),
+                };
+                // Keys like `1` and `"1"` end up with the same name
+                let mut seen = HashSet::new();
+                hash.iter()
+                    .map(|(key, value)| {
+                        let name = key_string(key);
+                        if !safe_name(&name) {
+                            Err(format!("The key {:?} can't be used as a file name", name))
+                        } else if !seen.insert(name.clone()) {
+                            Err(format!("More than one key would be written to {:?}", name))
+                        } else {
+                            Ok((name, value.clone()))
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    pub fn run(self) -> i32 {
+        let document = match Document::load(&self.filename) {
+            Ok(document) => document,
+            Err(error) => {
+                eprintln!("{}", error);
+                return 2;
+            }
+        };
+
+        let doc_type = self.force_format.unwrap_or_else(|| document.doc_type());
+        let pieces = match self.pieces(document.into_yaml()) {
+            Ok(pieces) => pieces,
+            Err(error) => {
+                eprintln!("{}", error);
+                return 2;
+            }
+        };
+
+        let outputs: Vec<_> = pieces
+            .into_iter()
+            .map(|(name, value)| {
+                (
+                    self.out_dir.join(format!("{}.{}", name, doc_type)),
+                    Document::from_yaml(doc_type, vec![value]),
+                )
+            })
+            .collect();
+
+        if let Err(error) = create_dir_all(&self.out_dir) {
+            eprintln!("Error creating {:?}: {}", self.out_dir, error);
+            return 2;
+        }
+
+        // Everything is rendered before writing, so nothing is written when
+        // a piece can't be
+        let rendered = outputs
+            .into_iter()
+            .map(|(name, document)| {
+                document
+                    .render()
+                    .map(|rendered| (name.clone(), rendered))
+                    .map_err(|error| format!("Can't write {:?}: {}", name, error))
+            })
+            .collect::<Result<Vec<_>, _>>();
+        let rendered = match rendered {
+            Ok(rendered) => rendered,
+            Err(error) => {
+                eprintln!("{}", error);
+                return 2;
+            }
+        };
+
+        for (name, rendered) in rendered {
+            if let Err(error) = write_atomically(&name, &rendered) {
+                eprintln!("Error writing {:?}: {}", name, error);
+                return 2;
+            }
+        }
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn split_args(by: SplitBy, path: Option<&str>) -> SplitArgs {
+        SplitArgs {
+            filename: "values.yaml".into(),
+            by,
+            path: path.map(|path| path.parse().unwrap()),
+            out_dir: "out".into(),
+            force_format: None,
+        }
+    }
+
+    fn load(source: &str) -> Vec<yamllib::Yaml> {
+        yamllib::YamlLoader::load_from_str(source).unwrap()
+    }
+
+    #[test_case("app", true)]
+    #[test_case("app.v1", true; "dots")]
+    #[test_case("", false; "empty")]
+    #[test_case(".", false; "current directory")]
+    #[test_case("..", false; "parent directory")]
+    #[test_case("a/b", false; "slash")]
+    #[test_case("a\\b", false; "backslash")]
+    fn test_safe_name(name: &str, expected: bool) {
+        assert_eq!(safe_name(name), expected);
+    }
+
+    #[test]
+    fn test_pieces() {
+        let pieces = split_args(SplitBy::TopLevelKey, Some("services"))
+            .pieces(load("services: {web: 1, db: {port: 5432}}"))
+            .unwrap();
+        assert_eq!(
+            pieces,
+            vec![
+                ("web".to_string(), load("1").remove(0)),
+                ("db".to_string(), load("port: 5432").remove(0)),
+            ]
+        );
+
+        let pieces = split_args(SplitBy::Document, None)
+            .pieces(load("a: 1\n---\nb: 2\n"))
+            .unwrap();
+        let names: Vec<_> = pieces.into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["values-0", "values-1"]);
+    }
+
+    #[test_case(SplitBy::TopLevelKey, None, "{1: a, '1': b}"; "duplicated names")]
+    #[test_case(SplitBy::TopLevelKey, None, "{../x: a}"; "unsafe name")]
+    #[test_case(SplitBy::TopLevelKey, None, "a: 1\n---\nb: 2\n"; "several documents")]
+    #[test_case(SplitBy::TopLevelKey, Some("a"), "a: 1"; "not a mapping")]
+    #[test_case(SplitBy::Document, Some("a"), "a: {b: 1}"; "path by document")]
+    fn test_pieces_errors(by: SplitBy, path: Option<&str>, source: &str) {
+        assert!(split_args(by, path).pieces(load(source)).is_err());
+    }
+}
@@ -0,0 +1,88 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+use strum::VariantNames;
+
+use crate::documents::{Document, DocumentType};
+use crate::merging::{unmerge, ArrayMergeBehavior};
+use crate::presets::Preset;
+
+#[derive(StructOpt, Debug)]
+pub(crate) struct UnmergeArgs {
+    /// The document the overlay will be merged onto.
+    #[structopt(name = "BASE", parse(from_os_str))]
+    base: PathBuf,
+
+    /// The document merging the overlay onto `BASE` has to produce.
+    #[structopt(name = "TARGET", parse(from_os_str))]
+    target: PathBuf,
+
+    /// How arrays will be merged, see the main command.
+    #[structopt(long = "arrays", default_value, possible_values = &ArrayMergeBehavior::VARIANTS)]
+    array_merge: ArrayMergeBehavior,
+
+    /// Compute the overlay for a preset's merging rules instead. Only `helm`
+    /// is supported, which allows removed keys to be expressed as `null`.
+    #[structopt(long = "preset", possible_values = &Preset::VARIANTS)]
+    preset: Option<Preset>,
+
+    /// Force output to be in a specific format, otherwise the format of
+    /// `TARGET` is used.
+    #[structopt(long = "force-format", possible_values = &DocumentType::VARIANTS)]
+    force_format: Option<DocumentType>,
+}
+
+impl UnmergeArgs {
+    pub fn run(self) -> i32 {
+        let (array_merge, null_deletes) = match self.preset {
+            None => (self.array_merge, false),
+            Some(Preset::Helm) => (ArrayMergeBehavior::Replace, true),
+            Some(preset) => {
+                eprintln!("The `{}` preset is not supported by `unmerge`", preset);
+                return 2;
+            }
+        };
+
+        let (base, target) = match (Document::load(&self.base), Document::load(&self.target)) {
+            (Ok(base), Ok(target)) => (base, target),
+            (Err(error), _) | (_, Err(error)) => {
+                eprintln!("{}", error);
+                return 2;
+            }
+        };
+
+        let doc_type = self.force_format.unwrap_or_else(|| target.doc_type());
+        let overlay = match unmerge(
+            &base.into_yaml(),
+            &target.into_yaml(),
+            array_merge,
+            null_deletes,
+        ) {
+            Ok(overlay) => overlay,
+            Err(problems) => {
+                for problem in problems {
+                    eprintln!(
+                        "Can't reproduce {}: {}",
+                        problem.path.describe(),
+                        problem.reason
+                    );
+                }
+                return 1;
+            }
+        };
+
+        let rendered = match Document::from_yaml(doc_type, overlay).render() {
+            Ok(rendered) => rendered,
+            Err(error) => {
+                eprintln!("{}", error);
+                return 2;
+            }
+        };
+        if let Err(error) = write!(io::stdout().lock(), "{}", rendered) {
+            eprintln!("Error writing to stdout: {}", error);
+            return 2;
+        }
+        0
+    }
+}
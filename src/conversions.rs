@@ -1,12 +1,18 @@
-// use toml;
+use std::convert::TryFrom;
+use std::mem::discriminant;
+
 use json as jsonlib;
+use toml as tomllib;
 use yaml_rust as yamllib;
 
+use crate::paths::ValuePath;
+
 #[derive(Debug, PartialEq)]
 pub(crate) struct YamlValue(pub yamllib::Yaml);
 #[derive(Debug, PartialEq)]
 pub(crate) struct JsonValue(pub jsonlib::JsonValue);
-// pub(crate) struct TomlValue(toml::Value);
+#[derive(Debug, PartialEq)]
+pub(crate) struct TomlValue(pub tomllib::Value);
 
 pub(crate) struct JsonObject(jsonlib::object::Object);
 pub(crate) struct YamlHash(yamllib::yaml::Hash);
@@ -35,6 +41,12 @@ impl Default for JsonValue {
     }
 }
 
+impl From<tomllib::Value> for TomlValue {
+    fn from(value: tomllib::Value) -> Self {
+        Self(value)
+    }
+}
+
 impl From<JsonObject> for YamlHash {
     fn from(JsonObject(value): JsonObject) -> Self {
         Self(
@@ -58,7 +70,14 @@ impl From<JsonValue> for YamlValue {
             jsonlib::JsonValue::Short(value) => yamllib::Yaml::String(value.into()),
 
             // TODO: Maybe figure out a way of not having to format a JSON number to string?
-            jsonlib::JsonValue::Number(value) => yamllib::Yaml::Real(value.to_string()),
+            // Integral numbers become integers, so they keep every digit
+            jsonlib::JsonValue::Number(value) => {
+                let value = value.to_string();
+                match value.parse::<i64>() {
+                    Ok(integer) => yamllib::Yaml::Integer(integer),
+                    Err(_) => yamllib::Yaml::Real(value),
+                }
+            }
             jsonlib::JsonValue::String(value) => yamllib::Yaml::String(value),
             jsonlib::JsonValue::Boolean(value) => yamllib::Yaml::Boolean(value),
             jsonlib::JsonValue::Object(value) => {
@@ -138,13 +157,158 @@ impl From<YamlValue> for JsonValue {
     }
 }
 
+impl From<TomlValue> for YamlValue {
+    fn from(TomlValue(value): TomlValue) -> Self {
+        YamlValue(match value {
+            tomllib::Value::String(value) => yamllib::Yaml::String(value),
+            tomllib::Value::Integer(value) => yamllib::Yaml::Integer(value),
+            // `{:?}` keeps the fractional part (`1.0`), so the value stays a real.
+            tomllib::Value::Float(value) => yamllib::Yaml::Real(format!("{:?}", value)),
+            tomllib::Value::Boolean(value) => yamllib::Yaml::Boolean(value),
+            tomllib::Value::Datetime(value) => yamllib::Yaml::String(value.to_string()),
+            tomllib::Value::Array(values) => yamllib::Yaml::Array(
+                values
+                    .into_iter()
+                    .map(|value| YamlValue::from(TomlValue(value)).0)
+                    .collect(),
+            ),
+            tomllib::Value::Table(table) => yamllib::Yaml::Hash(
+                table
+                    .into_iter()
+                    .map(|(key, value)| {
+                        (
+                            yamllib::Yaml::String(key),
+                            YamlValue::from(TomlValue(value)).0,
+                        )
+                    })
+                    .collect(),
+            ),
+        })
+    }
+}
+
+/// Not every YAML value can be written as TOML: TOML has no null, arrays
+/// can't mix types and keys can't be empty.
+impl TryFrom<YamlValue> for TomlValue {
+    type Error = String;
+
+    fn try_from(YamlValue(value): YamlValue) -> Result<Self, Self::Error> {
+        toml_value(value, &ValuePath::default()).map(TomlValue)
+    }
+}
+
+fn toml_value(value: yamllib::Yaml, path: &ValuePath) -> Result<tomllib::Value, String> {
+    Ok(match value {
+        // Reals without a fractional part are written as integers
+        yamllib::Yaml::Real(value) => match (value.parse::<i64>(), value.parse::<f64>()) {
+            (Ok(parsed), _) => tomllib::Value::Integer(parsed),
+            (_, Ok(parsed)) => tomllib::Value::Float(parsed),
+            _ => tomllib::Value::String(value),
+        },
+        yamllib::Yaml::Integer(value) => tomllib::Value::Integer(value),
+        yamllib::Yaml::String(value) => tomllib::Value::String(value),
+        yamllib::Yaml::Boolean(value) => tomllib::Value::Boolean(value),
+        yamllib::Yaml::Null => {
+            return Err(format!("{} is null, TOML has no null", path.describe()));
+        }
+        yamllib::Yaml::Hash(hash) => tomllib::Value::Table(
+            hash.into_iter()
+                .map(|(key, value)| {
+                    let key = match key {
+                        yamllib::Yaml::String(key) | yamllib::Yaml::Real(key) => key,
+                        yamllib::Yaml::Integer(key) => key.to_string(),
+                        yamllib::Yaml::Boolean(key) => key.to_string(),
+                        _ => return Err(format!("{} has a key TOML can't hold", path.describe())),
+                    };
+                    if key.is_empty() {
+                        return Err(format!("{} has an empty key", path.describe()));
+                    }
+                    let value = toml_value(value, &path.child(key.as_str()))?;
+                    Ok((key, value))
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+        yamllib::Yaml::Array(values) => {
+            let values = values
+                .into_iter()
+                .enumerate()
+                .map(|(index, value)| toml_value(value, &path.index(index)))
+                .collect::<Result<Vec<_>, _>>()?;
+            let mixed = values
+                .windows(2)
+                .any(|pair| discriminant(&pair[0]) != discriminant(&pair[1]));
+            if mixed {
+                return Err(format!(
+                    "{} mixes types, TOML arrays can't",
+                    path.describe()
+                ));
+            }
+            tomllib::Value::Array(values)
+        }
+
+        yamllib::Yaml::Alias(_) => panic!("`Yaml::Alias` is not yer supported"),
+        yamllib::Yaml::BadValue => panic!("`Yaml::BadValue` can not be converted"),
+    })
+}
+
+/// Formats a value on a single line (as JSON), for messages and reports.
+pub(crate) fn inline_value(value: &yamllib::Yaml) -> String {
+    JsonValue::from(YamlValue(value.clone())).0.dump()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use test_case::test_case;
 
     #[test]
     fn test_defaults() {
         assert_eq!(YamlValue::default(), YamlValue(yamllib::Yaml::Null));
         assert_eq!(JsonValue::default(), JsonValue(jsonlib::JsonValue::Null));
     }
+
+    #[test]
+    fn test_toml_round_trip() {
+        let toml = "a = 1\nb = 1.0\nc = 'x'\nd = [true, false]\n\n[e]\nf = 1979-05-27\n"
+            .parse::<tomllib::Value>()
+            .unwrap();
+        let yaml = YamlValue::from(TomlValue(toml.clone())).0;
+        assert_eq!(yaml["b"], yamllib::Yaml::Real("1.0".into()));
+        assert_eq!(yaml["e"]["f"], yamllib::Yaml::String("1979-05-27".into()));
+
+        let mut expected = toml;
+        expected["e"]["f"] = tomllib::Value::String("1979-05-27".into());
+        assert_eq!(TomlValue::try_from(YamlValue(yaml)).unwrap().0, expected);
+    }
+
+    #[test_case("{a: 1, b: 1.5, c: {d: [1, 2]}}", Ok("a = 1\nb = 1.5\n\n[c]\nd = [1, 2]\n"); "table")]
+    #[test_case("{a: ~}", Err("a is null, TOML has no null"); "null")]
+    #[test_case("{k: {l: [{x: ~}]}}", Err("k.l[0].x is null, TOML has no null"); "nested null")]
+    #[test_case("{a: [1, x]}", Err("a mixes types, TOML arrays can't"); "mixed array")]
+    #[test_case("{'': 1}", Err("(root) has an empty key"); "empty key")]
+    fn test_yaml_to_toml(yaml: &str, expected: Result<&str, &str>) {
+        let yaml = yamllib::YamlLoader::load_from_str(yaml).unwrap().remove(0);
+        assert_eq!(
+            TomlValue::try_from(YamlValue(yaml)).map(|toml| toml.0),
+            expected
+                .map(|toml| toml.parse::<tomllib::Value>().unwrap())
+                .map_err(String::from)
+        );
+    }
+
+    #[test]
+    fn test_json_integers() {
+        let json = jsonlib::parse(r#"{"big": 9007199254740993, "real": 1.5}"#).unwrap();
+        let yaml = YamlValue::from(JsonValue(json.clone())).0;
+        assert_eq!(yaml["big"], yamllib::Yaml::Integer(9007199254740993));
+        assert_eq!(yaml["real"], yamllib::Yaml::Real("1.5".into()));
+        assert_eq!(JsonValue::from(YamlValue(yaml)).0, json);
+    }
+
+    #[test]
+    fn test_json_integers_to_toml() {
+        let yaml = YamlValue::from(JsonValue(jsonlib::parse(r#"{"b": 1, "c": 2.5}"#).unwrap())).0;
+        let expected = "b = 1\nc = 2.5\n".parse::<tomllib::Value>().unwrap();
+        assert_eq!(TomlValue::try_from(YamlValue(yaml)).unwrap().0, expected);
+    }
 }
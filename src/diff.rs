@@ -0,0 +1,283 @@
+use std::fmt;
+
+use itertools::{EitherOrBoth, Itertools};
+use json as jsonlib;
+use yaml_rust as yamllib;
+
+use crate::conversions::{inline_value, JsonValue, YamlValue};
+use crate::paths::{key_string, ValuePath};
+
+/// A single difference between two documents. `document` is the position of
+/// the document in its stream.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Change {
+    Added {
+        document: usize,
+        path: ValuePath,
+        value: yamllib::Yaml,
+    },
+    Removed {
+        document: usize,
+        path: ValuePath,
+        value: yamllib::Yaml,
+    },
+    Changed {
+        document: usize,
+        path: ValuePath,
+        old: yamllib::Yaml,
+        new: yamllib::Yaml,
+    },
+}
+
+/// Integers, including reals written without a fractional part.
+fn as_integer(value: &yamllib::Yaml) -> Option<i128> {
+    match value {
+        yamllib::Yaml::Integer(value) => Some(i128::from(*value)),
+        yamllib::Yaml::Real(value) => value.parse().ok(),
+        _ => None,
+    }
+}
+
+fn as_number(value: &yamllib::Yaml) -> Option<f64> {
+    match value {
+        yamllib::Yaml::Integer(value) => Some(*value as f64),
+        yamllib::Yaml::Real(value) => value.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Compares two values the way a reader would: the order of mapping keys and
+/// the way numbers are written (`1`, `1.0`, `1e0`) don't matter.
+pub(crate) fn same_value(left: &yamllib::Yaml, right: &yamllib::Yaml) -> bool {
+    match (left, right) {
+        (yamllib::Yaml::Hash(left_hash), yamllib::Yaml::Hash(right_hash)) => {
+            left_hash.len() == right_hash.len()
+                && left_hash.iter().all(|(left_key, left_value)| {
+                    let left_key = key_string(left_key);
+                    right_hash.iter().any(|(right_key, right_value)| {
+                        key_string(right_key) == left_key && same_value(left_value, right_value)
+                    })
+                })
+        }
+        (yamllib::Yaml::Array(left_values), yamllib::Yaml::Array(right_values)) => {
+            left_values.len() == right_values.len()
+                && left_values
+                    .iter()
+                    .zip(right_values)
+                    .all(|(left, right)| same_value(left, right))
+        }
+        // Integers are compared exactly, as floats they'd lose precision
+        (left, right) => match (as_integer(left), as_integer(right)) {
+            (Some(left), Some(right)) => left == right,
+            _ => match (as_number(left), as_number(right)) {
+                (Some(left), Some(right)) => left == right,
+                _ => left == right,
+            },
+        },
+    }
+}
+
+fn diff_values(
+    document: usize,
+    path: ValuePath,
+    old: &yamllib::Yaml,
+    new: &yamllib::Yaml,
+    changes: &mut Vec<Change>,
+) {
+    match (old, new) {
+        (yamllib::Yaml::Hash(old_hash), yamllib::Yaml::Hash(new_hash)) => {
+            for (old_key, old_value) in old_hash {
+                let key = key_string(old_key);
+                match new_hash
+                    .iter()
+                    .find(|(new_key, _)| key_string(new_key) == key)
+                {
+                    Some((_, new_value)) => {
+                        diff_values(document, path.child(key), old_value, new_value, changes)
+                    }
+                    None => changes.push(Change::Removed {
+                        document,
+                        path: path.child(key),
+                        value: old_value.clone(),
+                    }),
+                }
+            }
+            for (new_key, new_value) in new_hash {
+                let key = key_string(new_key);
+                if !old_hash.keys().any(|old_key| key_string(old_key) == key) {
+                    changes.push(Change::Added {
+                        document,
+                        path: path.child(key),
+                        value: new_value.clone(),
+                    });
+                }
+            }
+        }
+        (yamllib::Yaml::Array(old_values), yamllib::Yaml::Array(new_values)) => {
+            for (index, zipped) in old_values.iter().zip_longest(new_values).enumerate() {
+                match zipped {
+                    EitherOrBoth::Both(old_value, new_value) => {
+                        diff_values(document, path.index(index), old_value, new_value, changes)
+                    }
+                    EitherOrBoth::Left(old_value) => changes.push(Change::Removed {
+                        document,
+                        path: path.index(index),
+                        value: old_value.clone(),
+                    }),
+                    EitherOrBoth::Right(new_value) => changes.push(Change::Added {
+                        document,
+                        path: path.index(index),
+                        value: new_value.clone(),
+                    }),
+                }
+            }
+        }
+        (old, new) if !same_value(old, new) => changes.push(Change::Changed {
+            document,
+            path,
+            old: old.clone(),
+            new: new.clone(),
+        }),
+        _ => {}
+    }
+}
+
+/// Lists every difference between two streams of documents.
+pub(crate) fn diff(old: &[yamllib::Yaml], new: &[yamllib::Yaml]) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for (document, zipped) in old.iter().zip_longest(new).enumerate() {
+        match zipped {
+            EitherOrBoth::Both(old, new) => {
+                diff_values(document, ValuePath::default(), old, new, &mut changes)
+            }
+            EitherOrBoth::Left(old) => changes.push(Change::Removed {
+                document,
+                path: ValuePath::default(),
+                value: old.clone(),
+            }),
+            EitherOrBoth::Right(new) => changes.push(Change::Added {
+                document,
+                path: ValuePath::default(),
+                value: new.clone(),
+            }),
+        }
+    }
+    changes
+}
+
+impl Change {
+    pub fn document(&self) -> usize {
+        match self {
+            Self::Added { document, .. }
+            | Self::Removed { document, .. }
+            | Self::Changed { document, .. } => *document,
+        }
+    }
+
+    pub fn path(&self) -> &ValuePath {
+        match self {
+            Self::Added { path, .. } | Self::Removed { path, .. } | Self::Changed { path, .. } => {
+                path
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> jsonlib::JsonValue {
+        let json = |value: &yamllib::Yaml| JsonValue::from(YamlValue(value.clone())).0;
+        let mut object = jsonlib::object::Object::new();
+        object.insert("document", self.document().into());
+        object.insert("path", self.path().to_string().into());
+        match self {
+            Self::Added { value, .. } => {
+                object.insert("op", "add".into());
+                object.insert("value", json(value));
+            }
+            Self::Removed { value, .. } => {
+                object.insert("op", "remove".into());
+                object.insert("value", json(value));
+            }
+            Self::Changed { old, new, .. } => {
+                object.insert("op", "change".into());
+                object.insert("old", json(old));
+                object.insert("new", json(new));
+            }
+        }
+        jsonlib::JsonValue::Object(object)
+    }
+}
+
+/// One line per change: `+` for additions, `-` for removals and `~` for
+/// changed values.
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self.path().describe();
+        match self {
+            Self::Added { value, .. } => write!(f, "+ {}: {}", path, inline_value(value)),
+            Self::Removed { value, .. } => write!(f, "- {}: {}", path, inline_value(value)),
+            Self::Changed { old, new, .. } => write!(
+                f,
+                "~ {}: {} -> {}",
+                path,
+                inline_value(old),
+                inline_value(new)
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn load(source: &str) -> Vec<yamllib::Yaml> {
+        yamllib::YamlLoader::load_from_str(source).unwrap()
+    }
+
+    #[test_case("{a: 1, b: 2}", "{b: 2, a: 1}"; "key order")]
+    #[test_case("{a: 1}", "{a: 1.0}"; "numbers")]
+    #[test_case("{1: x}", "{'1': x}"; "key types")]
+    fn test_no_changes(old: &str, new: &str) {
+        assert_eq!(diff(&load(old), &load(new)), vec![]);
+    }
+
+    #[test]
+    fn test_json_integers() {
+        let json = |source: &str| vec![YamlValue::from(JsonValue(json::parse(source).unwrap())).0];
+        let changes: Vec<String> = diff(
+            &load("{a: 9007199254740993}"),
+            &json(r#"{"a": 9007199254740992}"#),
+        )
+        .iter()
+        .map(Change::to_string)
+        .collect();
+        assert_eq!(changes, vec!["~ a: 9007199254740993 -> 9007199254740992"]);
+        assert_eq!(
+            diff(
+                &load("{a: 9007199254740993}"),
+                &json(r#"{"a": 9007199254740993}"#)
+            ),
+            vec![]
+        );
+    }
+
+    #[test_case(
+        "{a: 1, b: {c: 2}, l: [1, 2]}",
+        "{a: 2, b: {d: 3}, l: [1]}",
+        &["~ a: 1 -> 2", "- b.c: 2", "+ b.d: 3", "- l[1]: 2"]
+    )]
+    #[test_case("{a: 1}", "[1]", &["~ (root): {\"a\":1} -> [1]"]; "root")]
+    #[test_case(
+        "{a: 9007199254740993}",
+        "{a: 9007199254740992}",
+        &["~ a: 9007199254740993 -> 9007199254740992"];
+        "large integers"
+    )]
+    fn test_changes(old: &str, new: &str, expected: &[&str]) {
+        let changes: Vec<String> = diff(&load(old), &load(new))
+            .iter()
+            .map(Change::to_string)
+            .collect();
+        assert_eq!(changes, expected);
+    }
+}
@@ -1,105 +1,49 @@
+use std::convert::TryFrom;
 use std::error::Error;
-use std::fs::read_to_string;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::{self, read_to_string, OpenOptions};
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
 
 use itertools::{EitherOrBoth, Itertools};
 use strum_macros::{Display, EnumString, EnumVariantNames};
 
-use crate::conversions::{JsonValue, YamlValue};
+use crate::conversions::{JsonValue, TomlValue, YamlValue};
 use crate::merging::{ArrayMergeBehavior, DeepMerge};
+use crate::paths::ValuePath;
 
 use json as jsonlib;
-// use toml as tomllib;
+use toml as tomllib;
 use yaml_rust as yamllib;
 
-#[derive(Debug, Clone, Copy)]
-pub(crate) enum NamespaceWith {
-    Path,
-    Filename,
-    Basename,
-}
-
-impl std::str::FromStr for NamespaceWith {
-    type Err = &'static str;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "path" => Ok(NamespaceWith::Path),
-            "filename" => Ok(NamespaceWith::Filename),
-            "basename" => Ok(NamespaceWith::Basename),
-            _ => Err("Invalid wrap with option"),
-        }
-    }
-}
-
-impl NamespaceWith {
-    #![allow(clippy::trivially_copy_pass_by_ref)]
-    pub(crate) fn wrap<P: AsRef<Path>>(&self, document: Document, path: P) -> Document {
-        let namespace: String = match self {
-            NamespaceWith::Path => path.as_ref().to_string_lossy().into(),
-            NamespaceWith::Filename => {
-                let path_ref = path.as_ref();
-                path_ref
-                    .file_name()
-                    .unwrap_or_else(|| path_ref.as_os_str())
-                    .to_string_lossy()
-                    .into()
-            }
-            NamespaceWith::Basename => {
-                let mut path = path.as_ref().to_owned();
-                if path.set_extension("") {
-                    path.file_name()
-                        .unwrap_or_else(|| path.as_os_str())
-                        .to_string_lossy()
-                        .into()
-                } else {
-                    path.as_os_str().to_string_lossy().into()
-                }
-            }
-        };
-
-        match document {
-            Document::Yaml(yaml_doc) => {
-                let mut namespace_hash = yamllib::yaml::Hash::new();
-                namespace_hash.insert(
-                    yamllib::Yaml::String(namespace),
-                    match &yaml_doc[..] {
-                        [] => yamllib::Yaml::Null,
-                        [v] => v.clone(),
-                        _ => yamllib::Yaml::Array(yaml_doc),
-                    },
-                );
-                Document::Yaml(vec![yamllib::Yaml::Hash(namespace_hash)])
-            }
-            Document::Json(json_value) => {
-                // todo!()
-                let mut namespace_obj = jsonlib::object::Object::new();
-                namespace_obj.insert(
-                    &namespace,
-                    match &json_value[..] {
-                        [] => jsonlib::JsonValue::Null,
-                        [v] => v.clone(),
-                        _ => jsonlib::JsonValue::Array(json_value),
-                    },
-                );
-                Document::Json(vec![jsonlib::JsonValue::Object(namespace_obj)])
-            }
-        }
-    }
-}
-
-#[derive(Debug, Clone, Copy, Display, EnumString, EnumVariantNames)]
+#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString, EnumVariantNames)]
 #[strum(serialize_all = "kebab-case")]
 pub(crate) enum DocumentType {
     Yaml,
-    // TOML,
+    Toml,
     Json,
 }
 
 impl DocumentType {
+    /// Infers the document type from the extension of `filename`.
+    pub fn from_path<P: AsRef<Path>>(filename: P) -> Result<Self, DocumentError> {
+        filename
+            .as_ref()
+            .extension()
+            .and_then(OsStr::to_str)
+            .and_then(|extension| Self::from_str(extension).ok())
+            .ok_or_else(|| DocumentError::Skipped {
+                filename: filename.as_ref().into(),
+            })
+    }
+
     pub fn default_document(self) -> Document {
         match self {
             Self::Yaml => Document::Yaml(vec![YamlValue::default().0]),
+            Self::Toml => Document::Toml(vec![yamllib::Yaml::Hash(yamllib::yaml::Hash::new())]),
             Self::Json => Document::Json(vec![JsonValue::default().0]),
         }
     }
@@ -122,6 +66,13 @@ impl DocumentType {
                     filename: filename.as_ref().into(),
                     error: Box::new(error),
                 }),
+            Self::Toml => content
+                .parse::<tomllib::Value>()
+                .map(|loaded| Document::Toml(vec![YamlValue::from(TomlValue(loaded)).0]))
+                .map_err(|error| DocumentError::Loading {
+                    filename: filename.as_ref().into(),
+                    error: Box::new(error),
+                }),
             Self::Json => jsonlib::parse(content.as_ref())
                 .map(|loaded| Document::Json(vec![loaded]))
                 .map_err(|error| DocumentError::Loading {
@@ -143,6 +94,14 @@ impl DocumentType {
                     filename: filename.as_ref().into(),
                     error: Box::new(error),
                 }),
+            Self::Toml => content
+                .as_ref()
+                .parse::<tomllib::Value>()
+                .map(|loaded| Document::Toml(vec![YamlValue::from(TomlValue(loaded)).0]))
+                .map_err(|error| DocumentError::Loading {
+                    filename: filename.as_ref().into(),
+                    error: Box::new(error),
+                }),
             Self::Json => jsonlib::parse(content.as_ref())
                 .map(|loaded| Document::Json(vec![loaded]))
                 .map_err(|error| DocumentError::Loading {
@@ -153,10 +112,44 @@ impl DocumentType {
     }
 }
 
+/// Replaces `filename` with `contents` by writing a temporary file next to it
+/// and renaming it over the original, so readers never see a half-written
+/// file. The permissions of an existing file are kept.
+pub(crate) fn write_atomically<P: AsRef<Path>>(filename: P, contents: &str) -> io::Result<()> {
+    let filename = filename.as_ref();
+    let name = filename
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file name"))?;
+    let mut temporary_name = OsString::from(".");
+    temporary_name.push(name);
+    temporary_name.push(format!(".{}.tmp", process::id()));
+    let temporary = filename.with_file_name(temporary_name);
+
+    let written = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temporary)
+        .and_then(|mut file| {
+            file.write_all(contents.as_bytes())?;
+            if let Ok(metadata) = fs::metadata(filename) {
+                file.set_permissions(metadata.permissions())?;
+            }
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temporary, filename));
+
+    if written.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    written
+}
+
 #[derive(Debug, Clone)]
 pub(crate) enum Document {
     Yaml(Vec<yamllib::Yaml>),
-    // TOML(Vec<tomllib::Value>),
+    /// Kept as YAML values until it's rendered, since not every YAML value
+    /// can be written as TOML.
+    Toml(Vec<yamllib::Yaml>),
     Json(Vec<jsonlib::JsonValue>),
 }
 
@@ -171,7 +164,106 @@ pub(crate) enum DocumentError {
     },
 }
 
+impl fmt::Display for DocumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Skipped { filename } => write!(f, "Skipped {:?}", filename),
+            Self::Loading { filename, error } => {
+                write!(f, "Error loading {:?}: {}", filename, error)
+            }
+        }
+    }
+}
+
 impl Document {
+    /// Loads `filename`, inferring its format from the extension.
+    pub fn load<P: AsRef<Path>>(filename: P) -> Result<Self, DocumentError> {
+        DocumentType::from_path(filename.as_ref())?.load_from_path(filename)
+    }
+
+    /// Serializes every document in the stream, one after the other. Fails
+    /// for values the format can't hold.
+    pub fn render(&self) -> Result<String, String> {
+        let mut rendered = String::new();
+        match self {
+            Self::Json(json) => json.iter().for_each(|doc| {
+                rendered.push_str(&doc.pretty(2));
+                rendered.push('\n');
+            }),
+            Self::Yaml(yaml) => yaml.iter().for_each(|doc| {
+                let mut emitter = yamllib::YamlEmitter::new(&mut rendered);
+                emitter.dump(doc).unwrap();
+                rendered.push('\n');
+            }),
+            Self::Toml(toml) => {
+                for doc in toml {
+                    if !matches!(doc, yamllib::Yaml::Hash(_)) {
+                        return Err("Only mappings can be written as TOML".into());
+                    }
+                    let value = TomlValue::try_from(YamlValue(doc.clone()))?.0;
+                    rendered.push_str(
+                        &tomllib::to_string_pretty(&value).map_err(|error| error.to_string())?,
+                    );
+                }
+            }
+        }
+        Ok(rendered)
+    }
+
+    /// Moves the stream under `path`, creating the intermediate mappings
+    /// (and arrays). A stream with more than one document is moved as an
+    /// array.
+    pub fn nested(self, path: &ValuePath) -> Self {
+        if path.0.is_empty() {
+            return self;
+        }
+        let doc_type = self.doc_type();
+        let mut yaml = self.into_yaml();
+        let value = match yaml.len() {
+            0 => yamllib::Yaml::Null,
+            1 => yaml.remove(0),
+            _ => yamllib::Yaml::Array(yaml),
+        };
+        let mut root = yamllib::Yaml::Hash(yamllib::yaml::Hash::new());
+        path.set(&mut root, value);
+        Self::from_yaml(doc_type, vec![root])
+    }
+
+    pub fn doc_type(&self) -> DocumentType {
+        match self {
+            Self::Yaml(_) => DocumentType::Yaml,
+            Self::Toml(_) => DocumentType::Toml,
+            Self::Json(_) => DocumentType::Json,
+        }
+    }
+
+    /// Converts every document in the stream to YAML values, which is the
+    /// representation used by the operations that work on paths.
+    pub fn into_yaml(self) -> Vec<yamllib::Yaml> {
+        match self {
+            Self::Yaml(yaml) => yaml,
+            Self::Toml(yaml) => yaml,
+            Self::Json(json) => json
+                .into_iter()
+                .map(|value| YamlValue::from(JsonValue(value)).0)
+                .collect(),
+        }
+    }
+
+    /// The inverse of `into_yaml`: builds a document of the given type out of
+    /// YAML values.
+    pub fn from_yaml(doc_type: DocumentType, yaml: Vec<yamllib::Yaml>) -> Self {
+        match doc_type {
+            DocumentType::Yaml => Self::Yaml(yaml),
+            DocumentType::Toml => Self::Toml(yaml),
+            DocumentType::Json => Self::Json(
+                yaml.into_iter()
+                    .map(|value| JsonValue::from(YamlValue(value)).0)
+                    .collect(),
+            ),
+        }
+    }
+
     pub fn deep_merge(self, with: Self, array_merge: ArrayMergeBehavior) -> Self {
         match (self, with) {
             (Self::Yaml(left), Self::Yaml(right)) => Self::Yaml(
@@ -230,6 +322,31 @@ impl Document {
                     })
                     .collect(),
             ),
+            // TOML is merged as YAML, and the result keeps the type of the
+            // left-hand side.
+            (left, right) => {
+                let doc_type = left.doc_type();
+                let merged = Self::Yaml(left.into_yaml())
+                    .deep_merge(Self::Yaml(right.into_yaml()), array_merge);
+                Self::from_yaml(doc_type, merged.into_yaml())
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_keeps_json_integers() {
+        let document = DocumentType::Json
+            .load_from_str(r#"{"id": 9007199254740993}"#, "big.json")
+            .unwrap()
+            .nested(&"big".parse().unwrap());
+        assert_eq!(
+            document.render().unwrap(),
+            "{\n  \"big\": {\n    \"id\": 9007199254740993\n  }\n}\n"
+        );
+    }
+}
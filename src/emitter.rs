@@ -0,0 +1,172 @@
+use yaml_rust as yamllib;
+
+use crate::paths::ValuePath;
+
+/// Renders a scalar the way `yaml_rust::YamlEmitter` does (quoting and
+/// escaping strings as needed), by dumping it as a document of its own.
+fn scalar(node: &yamllib::Yaml) -> String {
+    let mut out = String::new();
+    yamllib::YamlEmitter::new(&mut out).dump(node).unwrap();
+    out.trim_start_matches("---").trim_start().to_string()
+}
+
+/// A block style YAML emitter that can end lines with comments. Its output
+/// matches `yaml_rust::YamlEmitter` (in compact mode) when no comments are
+/// given.
+struct CommentingEmitter<F> {
+    out: String,
+    level: isize,
+    comment: F,
+}
+
+impl<F: Fn(&ValuePath) -> Option<String>> CommentingEmitter<F> {
+    fn write_indent(&mut self) {
+        for _ in 0..self.level.max(0) {
+            self.out.push_str("  ");
+        }
+    }
+
+    fn write_comment(&mut self, path: &ValuePath) {
+        if let Some(comment) = (self.comment)(path) {
+            self.out.push_str(" # ");
+            self.out.push_str(&comment);
+        }
+    }
+
+    fn emit_scalar(&mut self, node: &yamllib::Yaml) {
+        match node {
+            yamllib::Yaml::Null | yamllib::Yaml::BadValue | yamllib::Yaml::Alias(_) => {
+                self.out.push('~')
+            }
+            value => self.out.push_str(&scalar(value)),
+        }
+    }
+
+    fn emit_node(&mut self, node: &yamllib::Yaml, path: &ValuePath) {
+        match node {
+            yamllib::Yaml::Array(values) if !values.is_empty() => self.emit_array(values, path),
+            yamllib::Yaml::Hash(hash) if !hash.is_empty() => self.emit_hash(hash, path),
+            leaf => {
+                self.emit_scalar(leaf);
+                self.write_comment(path);
+            }
+        }
+    }
+
+    fn emit_array(&mut self, values: &[yamllib::Yaml], path: &ValuePath) {
+        self.level += 1;
+        for (index, value) in values.iter().enumerate() {
+            if index > 0 {
+                self.out.push('\n');
+                self.write_indent();
+            }
+            self.out.push('-');
+            self.emit_value(true, value, &path.index(index));
+        }
+        self.level -= 1;
+    }
+
+    fn emit_hash(&mut self, hash: &yamllib::yaml::Hash, path: &ValuePath) {
+        self.level += 1;
+        for (index, (key, value)) in hash.iter().enumerate() {
+            if index > 0 {
+                self.out.push('\n');
+                self.write_indent();
+            }
+            match key {
+                yamllib::Yaml::Hash(_) | yamllib::Yaml::Array(_) => {
+                    // Complex keys can't be addressed by paths, so they don't
+                    // get comments
+                    self.out.push('?');
+                    self.emit_value(true, key, &path.child("?"));
+                    self.out.push('\n');
+                    self.write_indent();
+                    self.out.push(':');
+                    self.emit_value(true, value, &path.child_key(key));
+                }
+                key => {
+                    self.emit_scalar(key);
+                    self.out.push(':');
+                    self.emit_value(false, value, &path.child_key(key));
+                }
+            }
+        }
+        self.level -= 1;
+    }
+
+    fn emit_value(&mut self, inline: bool, value: &yamllib::Yaml, path: &ValuePath) {
+        let container = match value {
+            yamllib::Yaml::Array(values) => !values.is_empty(),
+            yamllib::Yaml::Hash(hash) => !hash.is_empty(),
+            _ => false,
+        };
+
+        if container && !inline {
+            // The container's own comment goes on the line of its key
+            self.write_comment(path);
+            self.out.push('\n');
+            self.level += 1;
+            self.write_indent();
+            self.level -= 1;
+        } else {
+            self.out.push(' ');
+        }
+        self.emit_node(value, path);
+    }
+}
+
+/// Emits a document like `yaml_rust::YamlEmitter::dump` does, ending the line
+/// of every node with the comment `comment` returns for its path (if any).
+pub(crate) fn emit_with_comments<F>(document: &yamllib::Yaml, comment: F) -> String
+where
+    F: Fn(&ValuePath) -> Option<String>,
+{
+    let mut emitter = CommentingEmitter {
+        out: String::from("---\n"),
+        level: -1,
+        comment,
+    };
+    emitter.emit_node(document, &ValuePath::default());
+    emitter.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn load(source: &str) -> yamllib::Yaml {
+        yamllib::YamlLoader::load_from_str(source)
+            .unwrap()
+            .remove(0)
+    }
+
+    #[test_case("a: 1\nb: {c: [1, {d: x, e: [y]}], f: {}}\ng: []\n"; "nested")]
+    #[test_case("- [1, 2]\n- {a: '1', b: 'yes', c: 'x: y', d: \"multi\\nline\"}\n"; "quoting")]
+    #[test_case("42"; "scalar")]
+    #[test_case("a: ''\nb: ' x'\nc: '~'\nd: '0x1f'\ne: '1e3'\nf: '.5'\ng: \"t\\tb\"\nh: '-x'\n"; "special strings")]
+    #[test_case("a: 1.5\nb: true\nc: ~\n"; "other scalars")]
+    fn test_matches_yaml_emitter(source: &str) {
+        let document = load(source);
+        let mut expected = String::new();
+        yamllib::YamlEmitter::new(&mut expected)
+            .dump(&document)
+            .unwrap();
+        assert_eq!(emit_with_comments(&document, |_| None), expected);
+    }
+
+    #[test]
+    fn test_comments() {
+        let document = load("a: 1\nb:\n  c: [x]\n");
+        let emitted = emit_with_comments(&document, |path| match path.to_string().as_str() {
+            "a" => Some("first".into()),
+            "b" => Some("second".into()),
+            "b.c[0]" => Some("third".into()),
+            _ => None,
+        });
+        assert_eq!(
+            emitted,
+            "---\na: 1 # first\nb: # second\n  c:\n    - x # third"
+        );
+    }
+}
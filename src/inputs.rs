@@ -0,0 +1,757 @@
+use std::cmp::Ordering;
+use std::fs::read_dir;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::str::{Chars, FromStr};
+
+use glob::{glob_with, MatchOptions, Pattern};
+use strum::VariantNames;
+
+use crate::archives::{self, inner_path, is_archive, is_compressed, read_entry, read_files};
+use crate::documents::{Document, DocumentError, DocumentType};
+use crate::merging::ArrayMergeBehavior;
+use crate::paths::ValuePath;
+use crate::sources::{git_show, sniff, Environment, ExternalCommand, Source};
+
+/// Suffixes of the files editors and package managers leave behind.
+const BACKUP_SUFFIXES: &[&str] = &[
+    "~",
+    ".swp",
+    ".swo",
+    ".bak",
+    ".orig",
+    ".rej",
+    ".tmp",
+    ".dpkg-old",
+    ".dpkg-dist",
+    ".rpmnew",
+    ".rpmsave",
+];
+
+/// An input argument: where to read it from, along with the options that
+/// apply to it alone. Written as `[FORMAT:]PATH[@KEY=VALUE,...]`, like
+/// `json:-` or `config.txt@format=yaml,arrays=concat,namespace=db`, or
+/// `into=a.b` to merge it under a nested path. Sources other than files
+/// are written with their own prefix, like `env:APP_`, `git:HEAD:a.yaml` or
+/// `cmd:terraform output -json`, and files in archives as
+/// `bundle.tar.gz#path/in/archive.yaml`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct InputSpec {
+    pub path: PathBuf,
+    pub source: Source,
+    pub format: Option<DocumentType>,
+    pub array_merge: Option<ArrayMergeBehavior>,
+    pub namespace: Option<String>,
+    pub into: Option<ValuePath>,
+}
+
+impl InputSpec {
+    fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            source: Source::File,
+            format: None,
+            array_merge: None,
+            namespace: None,
+            into: None,
+        }
+    }
+
+    /// A document given on the command line. The whole spec names it.
+    pub fn inline(doc_type: DocumentType, text: &str) -> Self {
+        Self {
+            source: Source::Inline(text.into()),
+            format: Some(doc_type),
+            ..Self::new(format!("inline:{}:{}", doc_type, text))
+        }
+    }
+
+    /// How the input is called in messages and explanations.
+    pub fn name(&self) -> String {
+        match &self.source {
+            Source::Git { rev } => format!("git:{}:{}", rev, self.path.to_string_lossy()),
+            Source::Archive { entry, .. } => format!(
+                "{}#{}",
+                self.path.to_string_lossy(),
+                entry.to_string_lossy()
+            ),
+            _ => self.path.to_string_lossy().into_owned(),
+        }
+    }
+
+    /// Reads the document of any input but stdin, along with the text it
+    /// was parsed from (if there's one).
+    pub fn load(&self) -> Result<(Document, Option<String>), DocumentError> {
+        let loading_error = |error| DocumentError::Loading {
+            filename: self.name().into(),
+            error,
+        };
+
+        // Inputs whose format can't be inferred are skipped before reading,
+        // except for commands, whose output is sniffed. Compressed files
+        // are inferred from their inner extension.
+        let inferred = |path: &Path| {
+            self.format.map(Ok).unwrap_or_else(|| {
+                DocumentType::from_path(inner_path(path)).map_err(|_| DocumentError::Skipped {
+                    filename: self.name().into(),
+                })
+            })
+        };
+        let doc_type = match &self.source {
+            Source::Environment(environment) => return Ok((environment.load(), None)),
+            Source::Command(_) => self.format,
+            Source::Archive { entry, .. } => Some(inferred(entry)?),
+            _ => Some(inferred(&self.path)?),
+        };
+        let text = match &self.source {
+            Source::File => archives::read_to_string(&self.path)
+                .map_err(|error| loading_error(Box::new(error)))?,
+            Source::Inline(text) => text.clone(),
+            Source::Git { rev } => git_show(rev, &self.path).map_err(loading_error)?,
+            Source::Command(command) => command.run().map_err(loading_error)?,
+            Source::Archive {
+                contents: Some(contents),
+                ..
+            } => contents.clone(),
+            Source::Archive {
+                entry,
+                contents: None,
+            } => read_entry(&self.path, entry).map_err(loading_error)?,
+            Source::Environment(_) => unreachable!(),
+        };
+        let document = doc_type
+            .unwrap_or_else(|| sniff(&text))
+            .load_from_str(&text, self.name())?;
+        Ok((document, Some(text)))
+    }
+
+    /// Whether this is stdin's placeholder, `-`.
+    pub fn is_stdin(&self) -> bool {
+        self.path == Path::new("-")
+    }
+
+    /// The same options, for another path (like a file found in a
+    /// directory).
+    fn with_path(&self, path: PathBuf) -> Self {
+        Self {
+            path,
+            ..self.clone()
+        }
+    }
+
+    fn qualify(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "format" => {
+                self.format = Some(DocumentType::from_str(value).map_err(|_| {
+                    format!(
+                        "Unknown format {:?}, expected one of {}",
+                        value,
+                        DocumentType::VARIANTS.join(", ")
+                    )
+                })?)
+            }
+            "arrays" => {
+                self.array_merge = Some(ArrayMergeBehavior::from_str(value).map_err(|_| {
+                    format!(
+                        "Unknown arrays behavior {:?}, expected one of {}",
+                        value,
+                        ArrayMergeBehavior::VARIANTS.join(", ")
+                    )
+                })?)
+            }
+            "namespace" => self.namespace = Some(value.into()),
+            "into" => self.into = Some(value.parse()?),
+            _ => {
+                let handled = match &mut self.source {
+                    Source::Environment(environment) => environment.qualify(key, value)?,
+                    Source::Command(command) => command.qualify(key, value)?,
+                    Source::File
+                    | Source::Inline(_)
+                    | Source::Git { .. }
+                    | Source::Archive { .. } => false,
+                };
+                if !handled {
+                    return Err(format!(
+                        "Unknown input qualifier {:?}, expected format, arrays, namespace or into \
+                         (or separator, case and types for `env:` inputs, and timeout for `cmd:` ones)",
+                        key
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for InputSpec {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        // Existing files are always taken literally
+        if Path::new(input).exists() {
+            return Ok(Self::new(input));
+        }
+
+        // Inline documents can hold anything, so they take no qualifiers
+        if let Some(text) = input.strip_prefix("inline:") {
+            return Ok(match text.split_once(':') {
+                Some((prefix, document)) => match DocumentType::from_str(prefix) {
+                    Ok(doc_type) => Self::inline(doc_type, document),
+                    Err(_) => Self::inline(DocumentType::Yaml, text),
+                },
+                None => Self::inline(DocumentType::Yaml, text),
+            });
+        }
+
+        let mut format = None;
+        let mut source = Source::File;
+        let mut rest = input;
+        if let Some((prefix, path)) = input.split_once(':') {
+            if let Ok(doc_type) = DocumentType::from_str(prefix) {
+                format = Some(doc_type);
+                rest = path;
+            } else if prefix == "env" {
+                // The prefix stays in the path, which names the input
+                source = Source::Environment(Environment::default());
+            } else if prefix == "cmd" {
+                // Like for `env:`, the command line is filled in below
+                source = Source::Command(ExternalCommand {
+                    arguments: Vec::new(),
+                    timeout: None,
+                });
+            } else if prefix == "git" {
+                let (rev, path) = path
+                    .split_once(':')
+                    .ok_or_else(|| format!("Expected git:REV:PATH, got {:?}", input))?;
+                source = Source::Git { rev: rev.into() };
+                rest = path;
+            }
+        }
+
+        // Only a suffix that looks like qualifiers is one, so other paths
+        // with an `@` still work.
+        let mut spec = Self {
+            source,
+            ..Self::new(rest)
+        };
+        if let Some((path, qualifiers)) = rest.rsplit_once('@') {
+            if !Path::new(rest).exists()
+                && !qualifiers.is_empty()
+                && qualifiers
+                    .split(',')
+                    .all(|qualifier| qualifier.contains('='))
+            {
+                spec.path = path.into();
+                for qualifier in qualifiers.split(',') {
+                    let (key, value) = qualifier.split_once('=').unwrap_or_default();
+                    spec.qualify(key, value)?;
+                }
+            }
+        }
+        spec.format = format.or(spec.format);
+        match &mut spec.source {
+            Source::Environment(environment) => {
+                environment.prefix = spec.path.to_string_lossy()["env:".len()..].into();
+            }
+            Source::Command(command) => {
+                command.arguments =
+                    ExternalCommand::from_str(&spec.path.to_string_lossy()["cmd:".len()..])?
+                        .arguments;
+            }
+            Source::File if !spec.path.exists() => {
+                let path = spec.path.to_string_lossy().into_owned();
+                if let Some((archive, entry)) = path.split_once('#') {
+                    if is_archive(Path::new(archive)) {
+                        spec.source = Source::Archive {
+                            entry: entry.into(),
+                            contents: None,
+                        };
+                        spec.path = archive.into();
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(spec)
+    }
+}
+
+/// Parses `--at PATH=FILE`: `FILE` (with its own qualifiers, if any) is
+/// merged under `PATH`. An `=` in `PATH` is escaped like in any path, as
+/// `\=`.
+pub(crate) fn parse_at(at: &str) -> Result<InputSpec, String> {
+    let mut escaped = false;
+    let split = at.char_indices().find_map(|(position, c)| {
+        let found = c == '=' && !escaped;
+        escaped = c == '\\' && !escaped;
+        if found {
+            Some(position)
+        } else {
+            None
+        }
+    });
+    let (path, input) = split
+        .map(|position| (&at[..position], &at[position + 1..]))
+        .ok_or_else(|| format!("Expected PATH=FILE, got {:?}", at))?;
+    let path: ValuePath = path.parse()?;
+    let mut spec: InputSpec = input.parse()?;
+    let mut into = path;
+    into.0
+        .extend(spec.into.take().map(|into| into.0).unwrap_or_default());
+    spec.into = Some(into);
+    Ok(spec)
+}
+
+/// How directories and glob patterns given as inputs are expanded.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ExpandOptions {
+    pub recursive: bool,
+    pub include_hidden: bool,
+    pub exclude: Vec<Pattern>,
+    pub allow_empty: bool,
+}
+
+impl ExpandOptions {
+    fn excluded(&self, path: &Path) -> bool {
+        self.exclude
+            .iter()
+            .any(|pattern| pattern.matches_path(path))
+    }
+}
+
+fn take_number(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(digit) = chars.peek().copied().filter(char::is_ascii_digit) {
+        digits.push(digit);
+        chars.next();
+    }
+    digits
+}
+
+/// Compares strings so that runs of digits are ordered by their numeric
+/// value: `2-x` goes before `10-y`.
+pub(crate) fn natural_cmp(left: &str, right: &str) -> Ordering {
+    let mut left = left.chars().peekable();
+    let mut right = right.chars().peekable();
+    loop {
+        match (left.peek().copied(), right.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(l), Some(r)) if l.is_ascii_digit() && r.is_ascii_digit() => {
+                let (l, r) = (take_number(&mut left), take_number(&mut right));
+                let (l_trimmed, r_trimmed) = (l.trim_start_matches('0'), r.trim_start_matches('0'));
+                let ordering = l_trimmed
+                    .len()
+                    .cmp(&r_trimmed.len())
+                    .then_with(|| l_trimmed.cmp(r_trimmed))
+                    // `01` and `1` are the same number, but still different names
+                    .then_with(|| l.len().cmp(&r.len()));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(l), Some(r)) => {
+                if l != r {
+                    return l.cmp(&r);
+                }
+                left.next();
+                right.next();
+            }
+        }
+    }
+}
+
+fn is_hidden(name: &str) -> bool {
+    name.starts_with('.')
+}
+
+fn is_backup(name: &str) -> bool {
+    BACKUP_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+        || (name.len() > 1 && name.starts_with('#') && name.ends_with('#'))
+}
+
+fn collect_directory(
+    directory: &Path,
+    options: &ExpandOptions,
+    any_format: bool,
+    found: &mut Vec<PathBuf>,
+) -> Result<(), DocumentError> {
+    let loading_error = |error| DocumentError::Loading {
+        filename: directory.into(),
+        error: Box::new(error),
+    };
+
+    let mut entries = read_dir(directory)
+        .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+        .map_err(loading_error)?;
+    entries.sort_by(|left, right| {
+        natural_cmp(
+            &left.file_name().to_string_lossy(),
+            &right.file_name().to_string_lossy(),
+        )
+    });
+
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if (is_hidden(&name) && !options.include_hidden) || is_backup(&name) {
+            continue;
+        }
+        let path = entry.path();
+        if options.excluded(&path) {
+            continue;
+        }
+        if path.is_dir() {
+            if options.recursive {
+                collect_directory(&path, options, any_format, found)?;
+            }
+        } else if any_format || DocumentType::from_path(inner_path(&path)).is_ok() {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Lists the files a directory given as input stands for, in the order
+/// `conf.d` directories are read: naturally sorted by name. Hidden files,
+/// backup files and (unless `any_format`) files whose format can't be
+/// inferred are left out.
+fn expand_directory(
+    directory: &Path,
+    options: &ExpandOptions,
+    any_format: bool,
+) -> Result<Vec<PathBuf>, DocumentError> {
+    let mut found = Vec::new();
+    collect_directory(directory, options, any_format, &mut found)?;
+    Ok(found)
+}
+
+/// Reads the files of an archive given as input, picked the same way as the
+/// files of a directory (see `expand_directory`).
+fn expand_archive(
+    archive: &Path,
+    options: &ExpandOptions,
+    any_format: bool,
+) -> Result<Vec<(PathBuf, String)>, DocumentError> {
+    let mut found = read_files(archive, |entry| {
+        let names: Vec<_> = entry
+            .iter()
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect();
+        let name = names.last().cloned().unwrap_or_default();
+        (names.len() == 1 || options.recursive)
+            && (options.include_hidden || !names.iter().any(|name| is_hidden(name)))
+            && !is_backup(&name)
+            && !options.excluded(entry)
+            && (any_format || DocumentType::from_path(inner_path(entry)).is_ok())
+    })
+    .map_err(|error| DocumentError::Loading {
+        filename: archive.into(),
+        error,
+    })?;
+    found.sort_by(|(left, _), (right, _)| {
+        natural_cmp(&left.to_string_lossy(), &right.to_string_lossy())
+    });
+    Ok(found)
+}
+
+/// Whether an input has to be expanded as a glob pattern. Existing files
+/// are taken literally, even when their names have special characters.
+fn is_pattern(input: &Path) -> bool {
+    input
+        .to_str()
+        .map(|input| input.contains(['*', '?', '[']))
+        .unwrap_or(false)
+        && !input.exists()
+}
+
+/// Lists the files matching a glob pattern (`**` matches any number of
+/// directories), naturally sorted so the order doesn't depend on the
+/// filesystem. Like in directories, files whose format can't be inferred are
+/// left out unless `any_format`.
+fn expand_pattern(
+    pattern: &Path,
+    options: &ExpandOptions,
+    any_format: bool,
+) -> Result<Vec<PathBuf>, DocumentError> {
+    let loading_error = |error: Box<dyn std::error::Error>| DocumentError::Loading {
+        filename: pattern.into(),
+        error,
+    };
+
+    let match_options = MatchOptions {
+        require_literal_leading_dot: !options.include_hidden,
+        ..MatchOptions::new()
+    };
+    let mut found: Vec<PathBuf> = glob_with(&pattern.to_string_lossy(), match_options)
+        .map_err(|error| loading_error(Box::new(error)))?
+        // Unreadable directories are just skipped, like `ls` would
+        .filter_map(Result::ok)
+        .collect();
+    found.retain(|path| {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        !path.is_dir()
+            && !is_backup(&name)
+            && !options.excluded(path)
+            && (any_format || DocumentType::from_path(inner_path(path)).is_ok())
+    });
+    found.sort_by(|left, right| natural_cmp(&left.to_string_lossy(), &right.to_string_lossy()));
+
+    if found.is_empty() && !options.allow_empty {
+        return Err(loading_error("the pattern matches no files".into()));
+    }
+    Ok(found)
+}
+
+/// Why an input can't be read from a git revision.
+pub(crate) const GIT_UNSUPPORTED: &str =
+    "directories, patterns, archives and compressed files can't be read from a git revision";
+
+/// Replaces directories and glob patterns in the inputs by the files they
+/// stand for, and archives by the files in them. They keep the options of
+/// the input. Anything else
+/// (including `-`, stdin, and sources other than files) is kept as it is.
+pub(crate) fn expand_inputs(
+    inputs: Vec<InputSpec>,
+    options: &ExpandOptions,
+) -> Result<Vec<InputSpec>, DocumentError> {
+    let mut expanded = Vec::new();
+    for input in inputs {
+        let paths = if let Source::Git { .. } = input.source {
+            // Revisions are read file by file, as text: there's nothing to
+            // list them or to decompress them
+            let is_directory = input.path.is_dir() || input.path.to_string_lossy().ends_with('/');
+            if is_directory
+                || is_pattern(&input.path)
+                || is_archive(&input.path)
+                || is_compressed(&input.path)
+            {
+                return Err(DocumentError::Loading {
+                    filename: input.name().into(),
+                    error: GIT_UNSUPPORTED.into(),
+                });
+            }
+            expanded.push(input);
+            continue;
+        } else if input.source != Source::File {
+            expanded.push(input);
+            continue;
+        } else if input.path.is_file() && is_archive(&input.path) {
+            let entries = expand_archive(&input.path, options, input.format.is_some())?;
+            expanded.extend(entries.into_iter().map(|(entry, contents)| InputSpec {
+                source: Source::Archive {
+                    entry,
+                    contents: Some(contents),
+                },
+                ..input.clone()
+            }));
+            continue;
+        } else if input.path.is_dir() {
+            expand_directory(&input.path, options, input.format.is_some())?
+        } else if is_pattern(&input.path) {
+            expand_pattern(&input.path, options, input.format.is_some())?
+        } else {
+            expanded.push(input);
+            continue;
+        };
+        expanded.extend(paths.into_iter().map(|path| input.with_path(path)));
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assignments::Typing;
+    use crate::sources::KeyCase;
+    use test_case::test_case;
+
+    #[test_case("2-x.yaml", "10-y.yaml", Ordering::Less; "numbers")]
+    #[test_case("a10", "a9", Ordering::Greater; "suffix numbers")]
+    #[test_case("01", "1", Ordering::Greater; "leading zeros")]
+    #[test_case("b", "a1", Ordering::Greater; "letters")]
+    #[test_case("a", "a", Ordering::Equal; "equal")]
+    fn test_natural_cmp(left: &str, right: &str, expected: Ordering) {
+        assert_eq!(natural_cmp(left, right), expected);
+    }
+
+    #[test_case("a.yaml", InputSpec::new("a.yaml"); "plain")]
+    #[test_case("json:-", InputSpec { format: Some(DocumentType::Json), ..InputSpec::new("-") }; "format prefix")]
+    #[test_case(
+        "config.txt@format=yaml,arrays=concat,namespace=db",
+        InputSpec {
+            format: Some(DocumentType::Yaml),
+            array_merge: Some(ArrayMergeBehavior::Concat),
+            namespace: Some("db".into()),
+            ..InputSpec::new("config.txt")
+        };
+        "qualifiers"
+    )]
+    #[test_case(
+        "config.txt@format=toml",
+        InputSpec { format: Some(DocumentType::Toml), ..InputSpec::new("config.txt") };
+        "toml format"
+    )]
+    #[test_case(
+        "secrets.yaml@into=app.credentials",
+        InputSpec { into: Some("app.credentials".parse().unwrap()), ..InputSpec::new("secrets.yaml") };
+        "into"
+    )]
+    #[test_case(
+        "env:APP_@separator=_,case=preserve,types=string",
+        InputSpec {
+            source: Source::Environment(Environment {
+                prefix: "APP_".into(),
+                separator: "_".into(),
+                case: KeyCase::Preserve,
+                typing: Typing::String,
+            }),
+            ..InputSpec::new("env:APP_")
+        };
+        "environment"
+    )]
+    #[test_case(r#"inline:json:{"a@b": "c=d"}"#, InputSpec::inline(DocumentType::Json, r#"{"a@b": "c=d"}"#); "inline")]
+    #[test_case("inline:a: {b: 1}", InputSpec::inline(DocumentType::Yaml, "a: {b: 1}"); "inline yaml")]
+    #[test_case(
+        "git:HEAD~1:config/app.txt@format=yaml",
+        InputSpec {
+            source: Source::Git { rev: "HEAD~1".into() },
+            format: Some(DocumentType::Yaml),
+            ..InputSpec::new("config/app.txt")
+        };
+        "git"
+    )]
+    #[test_case(
+        "cmd:kubectl get -o yaml 'config map'@timeout=30",
+        InputSpec {
+            source: Source::Command(ExternalCommand {
+                arguments: vec!["kubectl".into(), "get".into(), "-o".into(), "yaml".into(), "config map".into()],
+                timeout: Some(std::time::Duration::from_secs(30)),
+            }),
+            ..InputSpec::new("cmd:kubectl get -o yaml 'config map'")
+        };
+        "command"
+    )]
+    #[test_case(
+        "bundle.tar.gz#conf/app.yaml@arrays=concat",
+        InputSpec {
+            source: Source::Archive { entry: "conf/app.yaml".into(), contents: None },
+            array_merge: Some(ArrayMergeBehavior::Concat),
+            ..InputSpec::new("bundle.tar.gz")
+        };
+        "archive entry"
+    )]
+    #[test_case("notes#1.yaml", InputSpec::new("notes#1.yaml"); "not an archive")]
+    #[test_case("user@host.yaml", InputSpec::new("user@host.yaml"); "not qualifiers")]
+    #[test_case("toml:a.toml", InputSpec { format: Some(DocumentType::Toml), ..InputSpec::new("a.toml") }; "toml prefix")]
+    #[test_case("ini:a.ini", InputSpec::new("ini:a.ini"); "unknown prefix")]
+    fn test_parse_input(input: &str, expected: InputSpec) {
+        assert_eq!(input.parse::<InputSpec>(), Ok(expected));
+    }
+
+    #[test_case("a.yaml@format=ini"; "unknown format")]
+    #[test_case("a.yaml@arrays=zip"; "unknown arrays")]
+    #[test_case("a.yaml@colour=red"; "unknown qualifier")]
+    #[test_case("a.yaml@case=lower"; "environment qualifier")]
+    #[test_case("env:APP_@case=camel"; "unknown case")]
+    #[test_case("git:app.yaml"; "git without revision")]
+    #[test_case("cmd:echo 'a"; "unclosed quote")]
+    #[test_case("cmd:sleep 5@timeout=soon"; "invalid timeout")]
+    #[test_case("cmd:sleep 5@timeout=1e30"; "huge timeout")]
+    #[test_case("cmd:sleep 5@timeout=inf"; "infinite timeout")]
+    fn test_parse_input_errors(input: &str) {
+        assert!(input.parse::<InputSpec>().is_err());
+    }
+
+    #[test_case("spec.template.spec=pod.yaml", "spec.template.spec"; "plain")]
+    #[test_case("a=b.yaml@into=c", "a.c"; "combined")]
+    #[test_case(r"a\=b.c=d.yaml", r"a\=b.c"; "escaped equals")]
+    #[test_case(r"a\\=d.yaml", r"a\\"; "escaped backslash")]
+    fn test_parse_at(at: &str, expected: &str) {
+        assert_eq!(parse_at(at).unwrap().into, Some(expected.parse().unwrap()));
+    }
+
+    #[test_case("values.yaml~", true)]
+    #[test_case(".values.yaml.swp", true; "swap")]
+    #[test_case("#values.yaml#", true; "autosave")]
+    #[test_case("values.yaml", false; "regular")]
+    fn test_is_backup(name: &str, expected: bool) {
+        assert_eq!(is_backup(name), expected);
+    }
+
+    #[test]
+    fn test_expand_inputs() {
+        let root = std::env::temp_dir().join(format!("mungye-inputs-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("conf.d/sub")).unwrap();
+        for name in &[
+            "conf.d/10-b.yaml",
+            "conf.d/2-a.json",
+            "conf.d/.hidden.yaml",
+            "conf.d/2-a.json~",
+            "conf.d/sub/1.yaml",
+            "conf.d/notes.txt",
+        ] {
+            std::fs::write(root.join(name), "{}").unwrap();
+        }
+
+        let expand = |input: &str, options: &ExpandOptions| {
+            expand_inputs(vec![InputSpec::new(root.join(input))], options).map(|found| {
+                found
+                    .iter()
+                    .map(|InputSpec { path, .. }| {
+                        path.strip_prefix(&root)
+                            .unwrap()
+                            .to_string_lossy()
+                            .into_owned()
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+        let options = ExpandOptions::default();
+        let recursive = ExpandOptions {
+            recursive: true,
+            exclude: vec![Pattern::new("**/*.json").unwrap()],
+            ..ExpandOptions::default()
+        };
+
+        let directory = expand("conf.d", &options);
+        let recursive_directory = expand("conf.d", &recursive);
+        let pattern = expand("conf.d/**/*.yaml", &options);
+        let any_name = expand("conf.d/*", &options);
+        let no_matches = expand("conf.d/*.toml", &options);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            directory.unwrap(),
+            vec!["conf.d/2-a.json", "conf.d/10-b.yaml"]
+        );
+        assert_eq!(
+            recursive_directory.unwrap(),
+            vec!["conf.d/10-b.yaml", "conf.d/sub/1.yaml"]
+        );
+        assert_eq!(
+            pattern.unwrap(),
+            vec!["conf.d/10-b.yaml", "conf.d/sub/1.yaml"]
+        );
+        assert_eq!(
+            any_name.unwrap(),
+            vec!["conf.d/2-a.json", "conf.d/10-b.yaml"]
+        );
+        assert!(no_matches.is_err());
+    }
+
+    #[test_case("conf.d/"; "directory")]
+    #[test_case("conf.d/*.yaml"; "pattern")]
+    #[test_case("bundle.tar"; "archive")]
+    #[test_case("values.yaml.gz"; "compressed")]
+    fn test_expand_git_inputs(path: &str) {
+        let input = InputSpec {
+            source: Source::Git { rev: "HEAD".into() },
+            ..InputSpec::new(path)
+        };
+        assert!(expand_inputs(vec![input], &ExpandOptions::default()).is_err());
+    }
+}
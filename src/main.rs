@@ -1,32 +1,117 @@
+mod archives;
+mod assignments;
+mod check;
+mod commands;
 mod conversions;
+mod diff;
 mod documents;
+mod emitter;
+mod inputs;
 mod merging;
+mod namespaces;
+mod paths;
+mod presets;
+mod provenance;
+mod report;
+mod sources;
 
 use std::{
-    ffi::OsStr,
     io::{self, stdin, Read, Write},
     path::PathBuf,
-    str::FromStr,
 };
 
-use structopt::StructOpt;
+use structopt::{clap::AppSettings, StructOpt};
 use strum::VariantNames;
 
 use crate::{
-    documents::{Document, DocumentError, DocumentType, NamespaceWith},
+    archives::{is_archive, is_compressed},
+    assignments::{parse_set, parse_set_json, Assignment, Typing},
+    check::{stale, CheckMode},
+    commands::{Command, ReportFormat},
+    documents::{write_atomically, Document, DocumentError, DocumentType},
+    inputs::{expand_inputs, ExpandOptions, InputSpec},
     merging::ArrayMergeBehavior,
+    namespaces::NamespaceWith,
+    paths::ValuePath,
+    presets::Preset,
+    provenance::{line_map, prefix_lines, render_explanation, Provenance},
+    report::MergeReport,
+    sources::Source,
 };
 
 /// Command-line arguments for this tool
 #[derive(StructOpt, Debug)]
-#[structopt(name = "basic")]
+#[structopt(name = "basic", setting = AppSettings::SubcommandsNegateReqs)]
 struct CliArgs {
+    #[structopt(subcommand)]
+    command: Option<Command>,
+
     /// Files to process. Formats are inferred from the filename extension.
+    /// Directories stand for the files in them, in natural order (`2-x.yaml`
+    /// before `10-y.yaml`), like `conf.d` directories. Glob patterns
+    /// (`'overlays/**/*.yaml'`) are expanded too, sorted the same way.
+    /// Each file can be given its own options as `[FORMAT:]FILE[@KEY=VALUE,...]`,
+    /// where the keys are `format`, `arrays`, `namespace` and `into` (like
+    /// `json:-`, `config.txt@format=yaml,arrays=concat,namespace=db` or
+    /// `secrets.json@into=app.credentials`).
+    /// Other sources are given with a prefix: `env:PREFIX` for environment
+    /// variables (which also take `separator`, `case` and `types`),
+    /// `inline:[FORMAT:]TEXT` for a document written right in
+    /// the argument, `git:REV:FILE` for a file as it is in a git revision
+    /// and `cmd:COMMAND LINE` for the output of a command (run without a
+    /// shell, with an optional `@timeout=SECONDS`). Compressed files
+    /// (`.gz`, `.zst`, `.xz`) are decompressed, files in tar and zip
+    /// archives are given as `ARCHIVE#PATH` and whole archives are merged
+    /// like directories.
     /// A `-` (dash) can be used to indicate `stdin`, however two conditions apply:
-    /// 1. The `--stdin-format` argument has to be specified (so we know how to parse the incoming stream).
+    /// 1. Its format has to be given, with `--stdin-format` or a qualifier (so we know how to parse the incoming stream).
     /// 2. The dash can only be present at most once in the arguments list (because stdin can only be used once).
-    #[structopt(name = "FILE", parse(from_os_str), required = true)]
-    filenames: Vec<PathBuf>,
+    #[structopt(name = "FILE", required_unless_one = &["at", "json", "yaml"])]
+    inputs: Vec<InputSpec>,
+
+    /// Merge a file under a nested path of the result, creating the
+    /// intermediate mappings, like `--at spec.template.spec=pod.yaml`. It
+    /// is merged in its place among the `FILE` arguments. The same as the
+    /// `FILE@into=PATH` qualifier. An `=` in `PATH` is escaped as `\=`.
+    /// Can be repeated.
+    #[structopt(long = "at", number_of_values = 1, parse(try_from_str = inputs::parse_at))]
+    at: Vec<InputSpec>,
+
+    /// Merge a JSON document given as is, like `--json '{"debug":true}'`.
+    /// It is merged in its place among the `FILE` arguments, like
+    /// `inline:json:TEXT`. Can be repeated.
+    #[structopt(long = "json", number_of_values = 1)]
+    json: Vec<String>,
+
+    /// Merge a YAML document given as is, like `--yaml 'a: {b: 1}'`. The
+    /// same as `--json`, for YAML.
+    #[structopt(long = "yaml", number_of_values = 1)]
+    yaml: Vec<String>,
+
+    /// Read every file from this git revision (like `HEAD~1` or `main`)
+    /// instead of the working tree, like `git:REV:FILE` does for a single
+    /// one. Directories and glob patterns can't be used with it.
+    #[structopt(long = "rev")]
+    rev: Option<String>,
+
+    /// Also merge the files in the subdirectories of directory arguments.
+    #[structopt(short = "r", long = "recursive")]
+    recursive: bool,
+
+    /// Don't skip hidden files (and directories) found in directory
+    /// arguments. Backup files (`*~`, `*.swp`, `*.orig`...) are always
+    /// skipped.
+    #[structopt(long = "include-hidden")]
+    include_hidden: bool,
+
+    /// Leave out the files matching this glob pattern when expanding
+    /// directories and patterns. Can be repeated.
+    #[structopt(long = "exclude", number_of_values = 1, parse(try_from_str = glob::Pattern::new))]
+    exclude: Vec<glob::Pattern>,
+
+    /// Don't fail when a glob pattern matches no files.
+    #[structopt(long = "allow-empty")]
+    allow_empty: bool,
 
     /// How to handle arrays merging
     #[structopt(long = "arrays", default_value, possible_values = &ArrayMergeBehavior::VARIANTS)]
@@ -38,13 +123,137 @@ struct CliArgs {
     force_format: Option<DocumentType>,
 
     /// Defines the format for stdin data. This is required if the dash
-    /// (`-`, the stdin placeholder) is specified as a file argument.
-    /// Otherwise it is ignored.
+    /// (`-`, the stdin placeholder) is specified as a file argument without
+    /// a format of its own. Otherwise it is ignored.
     #[structopt(long = "stdin-format", possible_values = &DocumentType::VARIANTS)]
     stdin_format: Option<DocumentType>,
 
-    #[structopt(long = "namespace")]
+    /// Wrap each file under a key made from its `path`, its `filename` or
+    /// its `basename` (the file name without extension). `tree` nests it
+    /// following its directories instead: `conf/db/primary.yaml` ends up
+    /// under `conf.db.primary`.
+    #[structopt(long = "namespace", possible_values = &["path", "filename", "basename", "tree"])]
     namespace: Option<NamespaceWith>,
+
+    /// Wrap each file under a key rendered from this template. The
+    /// placeholders are `{path}`, `{dir}`, `{parent}` (the name of the
+    /// directory holding the file), `{filename}`, `{stem}` and `{ext}`, like
+    /// `{parent}_{stem}`.
+    #[structopt(long = "namespace-template", conflicts_with = "namespace")]
+    namespace_template: Option<String>,
+
+    /// Match the name of each file against this regex, so its groups can be
+    /// used by `--namespace-key`. Files that don't match are an error.
+    #[structopt(
+        long = "namespace-regex",
+        conflicts_with_all = &["namespace", "namespace-template"],
+        requires = "namespace-key"
+    )]
+    namespace_regex: Option<regex::Regex>,
+
+    /// The template for the key of each file when using
+    /// `--namespace-regex`, where groups can be used by name (`{env}`) or
+    /// number (`{1}`), besides the placeholders of `--namespace-template`.
+    #[structopt(long = "namespace-key", requires = "namespace-regex")]
+    namespace_key: Option<String>,
+
+    /// Wrap stdin too, as if it was read from a file with this name. Without
+    /// it, stdin is never wrapped.
+    #[structopt(long = "stdin-name", parse(from_os_str))]
+    stdin_name: Option<PathBuf>,
+
+    /// Directory stripped from the paths used by `--namespace=path` and
+    /// `--namespace=tree`, so a directory of fragments isn't nested under
+    /// its own name.
+    #[structopt(long = "namespace-root", parse(from_os_str))]
+    namespace_root: Option<PathBuf>,
+
+    /// Merge the files the way another tool layers its configuration.
+    /// `helm` mimics `helm -f ... -f ...`: arrays are replaced and a `null`
+    /// deletes the key. `openapi` combines the `paths`, `components` and
+    /// `tags` of a spec split across files, failing on conflicting operations
+    /// or duplicated `operationId`s. When a preset is given, `--arrays` is
+    /// ignored and inputs can't set `arrays`.
+    #[structopt(long = "preset", possible_values = &Preset::VARIANTS)]
+    preset: Option<Preset>,
+
+    /// Set values after all files are merged, like `a.b[0].c=x,d={1,2}`.
+    /// Values are typed like plain YAML scalars (`8080` is a number, `~` is
+    /// null), or like in Helm with `--preset=helm`, where assigning `null`
+    /// deletes the key. Intermediate mappings and arrays are created as
+    /// needed. Can be repeated, along with `--set-string`, `--set-json` and
+    /// `--unset`, which are all applied in the order they're given.
+    #[structopt(long = "set", number_of_values = 1)]
+    set: Vec<String>,
+
+    /// Like `--set`, but every value is a string.
+    #[structopt(long = "set-string", number_of_values = 1)]
+    set_string: Vec<String>,
+
+    /// Set the value at a path to a JSON document, like `tags=["a","b"]`.
+    #[structopt(long = "set-json", number_of_values = 1, parse(try_from_str = parse_set_json))]
+    set_json: Vec<Assignment>,
+
+    /// Remove the value at a path (like `items[2].name`) after all files are
+    /// merged.
+    #[structopt(long = "unset", number_of_values = 1)]
+    unset: Vec<ValuePath>,
+
+    /// Instead of the merged document, print where each of its values came
+    /// from: the file, the document in the file and the line.
+    #[structopt(long = "explain", conflicts_with_all = &["output", "in-place"])]
+    explain: bool,
+
+    /// Format for `--explain`: an aligned `text` table or `json`.
+    #[structopt(long = "explain-format", default_value, possible_values = &ReportFormat::VARIANTS)]
+    explain_format: ReportFormat,
+
+    /// Print the merged document as YAML with a trailing comment on every
+    /// value telling where it came from (`# from prod.yaml:12`), and noting
+    /// the values it overrode.
+    #[structopt(long = "annotate", conflicts_with = "explain")]
+    annotate: bool,
+
+    /// After merging, print to stderr what each input changed: the keys it
+    /// added, the ones it overrode (with the old and new values) and the
+    /// ones it deleted.
+    #[structopt(long = "report")]
+    report: bool,
+
+    /// Write the `--report` to this file instead of stderr.
+    #[structopt(long = "report-file", parse(from_os_str))]
+    report_file: Option<PathBuf>,
+
+    /// Write the result to this file instead of stdout. Its format is
+    /// inferred from the extension, unless `--force-format` is given. The
+    /// file is replaced atomically.
+    #[structopt(
+        short = "o",
+        long = "output",
+        parse(from_os_str),
+        conflicts_with = "in-place"
+    )]
+    output: Option<PathBuf>,
+
+    /// Write the result back into the first `FILE`, replacing it atomically.
+    #[structopt(long = "in-place")]
+    in_place: bool,
+
+    /// With `--in-place`, keep a copy of the original file, named like it
+    /// plus this suffix (e.g. `.orig`).
+    #[structopt(long = "backup", requires = "in-place")]
+    backup: Option<String>,
+
+    /// Don't write anything, check instead that the `--output` (or
+    /// `--in-place`) file already holds the result. When it doesn't, the
+    /// differences are printed and the exit code is 1.
+    #[structopt(long = "check", conflicts_with_all = &["backup", "report-file"])]
+    check: bool,
+
+    /// How `--check` compares: `semantic` ignores formatting, `bytes`
+    /// requires the file to be exactly what would be written.
+    #[structopt(long = "check-mode", default_value, possible_values = &CheckMode::VARIANTS)]
+    check_mode: CheckMode,
 }
 
 fn handle_stdout_error<T>(result: io::Result<T>) {
@@ -62,132 +271,501 @@ fn handle_stdout_error<T>(result: io::Result<T>) {
 }
 
 fn main() {
+    let matches = CliArgs::clap().get_matches();
     let CliArgs {
-        filenames,
+        command,
+        inputs,
+        at,
+        json,
+        yaml,
+        rev,
+        recursive,
+        include_hidden,
+        exclude,
+        allow_empty,
         array_merge,
         force_format,
         stdin_format,
-        namespace: wrap,
-    } = CliArgs::from_args();
-
-    let use_stdin = filenames
-        .iter()
-        .any(|filename| filename.to_str().map(|s| s == "-").unwrap_or(false));
-
-    if use_stdin && stdin_format.is_none() {
-        eprintln!("Error: `--stdin-format` must be set when `-` (stdin) is specified.");
-        // TODO: Get rid of `exit()` since this doesn't allow destructors to
-        // run properly. Change `main` to return a `Result<>`, also look into
-        // https://github.com/sgrif/terminator
+        namespace,
+        namespace_template,
+        namespace_regex,
+        namespace_key,
+        stdin_name,
+        namespace_root,
+        preset,
+        set,
+        set_string,
+        set_json,
+        unset,
+        explain,
+        explain_format,
+        annotate,
+        report,
+        report_file,
+        output,
+        in_place,
+        backup,
+        check,
+        check_mode,
+    } = CliArgs::from_clap(&matches);
+
+    if let Some(command) = command {
+        std::process::exit(command.run());
+    }
+
+    let expand_options = ExpandOptions {
+        recursive,
+        include_hidden,
+        exclude,
+        allow_empty,
+    };
+    // `--at` and inline inputs are merged in the order they were given
+    // among the others
+    let indices_of = |name| matches.indices_of(name).into_iter().flatten();
+    let inline = |doc_type| move |text: String| InputSpec::inline(doc_type, &text);
+    let mut positioned: Vec<(usize, InputSpec)> = indices_of("FILE")
+        .zip(inputs)
+        .chain(indices_of("at").zip(at))
+        .chain(indices_of("json").zip(json.into_iter().map(inline(DocumentType::Json))))
+        .chain(indices_of("yaml").zip(yaml.into_iter().map(inline(DocumentType::Yaml))))
+        .collect();
+    positioned.sort_by_key(|(index, _)| *index);
+    if let (Some(_), Some(input)) = (
+        &rev,
+        positioned
+            .iter()
+            .map(|(_, input)| input)
+            .find(|input| matches!(input.source, Source::Archive { .. })),
+    ) {
+        eprintln!("Error: {}: {}", input.name(), inputs::GIT_UNSUPPORTED);
         std::process::exit(1);
     }
+    let inputs = positioned
+        .into_iter()
+        .map(|(_, input)| match (&rev, &input.source) {
+            (Some(rev), Source::File) if !input.is_stdin() => InputSpec {
+                source: Source::Git { rev: rev.clone() },
+                ..input
+            },
+            _ => input,
+        })
+        .collect();
 
-    let stdin_doc_result = match (use_stdin, stdin_format) {
-        (true, Some(doc_type)) => {
-            let mut buffer = String::new();
+    let inputs = match expand_inputs(inputs, &expand_options) {
+        Ok(inputs) => inputs,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+
+    if let (Some(preset), Some(input)) = (
+        preset,
+        inputs.iter().find(|input| input.array_merge.is_some()),
+    ) {
+        eprintln!(
+            "Error: {:?} sets `arrays`, but the `{}` preset has its own rules for arrays.",
+            input.path, preset
+        );
+        std::process::exit(1);
+    }
+
+    let output = if in_place {
+        match inputs.first() {
+            Some(input) if input.source == Source::File && !input.is_stdin() => {
+                Some(input.path.clone())
+            }
+            _ => {
+                eprintln!("Error: `--in-place` needs the first `FILE` to be a file, not stdin or another source.");
+                std::process::exit(1);
+            }
+        }
+    } else {
+        output
+    };
+    // Outputs are written as plain text
+    if let Some(output) = output
+        .as_ref()
+        .filter(|output| is_compressed(output) || is_archive(output))
+    {
+        eprintln!(
+            "Error: can't write to {:?}, compressed files and archives can only be read.",
+            output
+        );
+        std::process::exit(1);
+    }
+    let output_format = force_format.or_else(|| {
+        output
+            .as_ref()
+            .and_then(|output| DocumentType::from_path(output).ok())
+    });
+
+    if check && output.is_none() {
+        eprintln!(
+            "Error: `--check` needs the file to check, given with `--output` or `--in-place`."
+        );
+        std::process::exit(1);
+    }
+
+    if annotate && matches!(output_format, Some(format) if format != DocumentType::Yaml) {
+        eprintln!("Error: `--annotate` can only output YAML.");
+        std::process::exit(1);
+    }
+
+    let null_deletes = matches!(preset, Some(Preset::Helm));
+    let typing = if null_deletes {
+        Typing::Helm
+    } else {
+        Typing::Yaml
+    };
+    // Every `--set*` and `--unset` is applied in the order it was given
+    let mut overrides: Vec<(usize, &str, Vec<Assignment>)> = Vec::new();
+    for (flag, expressions, typing) in &[
+        ("--set", &set, typing),
+        ("--set-string", &set_string, Typing::String),
+    ] {
+        for (index, expression) in indices_of(&flag[2..]).zip(*expressions) {
+            match parse_set(expression, *typing) {
+                Ok(assignments) => overrides.push((index, flag, assignments)),
+                Err(error) => {
+                    eprintln!("Error: {}", error);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    overrides.extend(
+        indices_of("set-json")
+            .zip(set_json)
+            .map(|(index, assignment)| (index, "--set-json", vec![assignment])),
+    );
+    overrides.extend(
+        indices_of("unset")
+            .zip(unset)
+            .map(|(index, path)| (index, "--unset", vec![Assignment::Unset(path)])),
+    );
+    overrides.sort_by_key(|(index, _, _)| *index);
+
+    let stdin_input = inputs.iter().find(|input| input.is_stdin());
+
+    let mut stdin_buffer = String::new();
+    let stdin_doc_result = match stdin_input.map(|input| input.format.or(stdin_format)) {
+        Some(Some(doc_type)) => {
+            let buffer = &mut stdin_buffer;
             stdin()
                 .lock()
-                .read_to_string(&mut buffer)
+                .read_to_string(buffer)
                 .map_err(|error| DocumentError::Loading {
                     filename: "-".into(),
                     error: Box::new(error),
                 })
-                .and_then(|_| doc_type.load_from_str(&buffer, PathBuf::from("-")))
+                .and_then(|_| doc_type.load_from_str(buffer, PathBuf::from("-")))
         }
-        (true, None) => {
-            eprintln!("Error: `--stdin-format` must be set when `-` (stdin) is specified.");
+        Some(None) => {
+            eprintln!(
+                "Error: `--stdin-format` must be set when `-` (stdin) is specified without a format (like `yaml:-`)."
+            );
             // TODO: Get rid of `exit()` since this doesn't allow destructors to
             // run properly. Change `main` to return a `Result<>`, also look into
             // https://github.com/sgrif/terminator
             std::process::exit(1);
         }
-        (false, _) => Err(DocumentError::Skipped {
+        None => Err(DocumentError::Skipped {
             filename: "-".into(),
         }),
     };
 
-    let mut documents = filenames.into_iter().filter_map(|filename| {
-        // Check if is this is stdin's placeholder
-        if filename.to_str().map(|s| s == "-").unwrap_or(false) {
-            // stdin's result is a singleton, but unfortunately most errors are
-            // not `Clone`, so we can't clone the entire result, which means
-            // we need to handle stdin's processing right here.
-            match stdin_doc_result.as_ref() {
-                Ok(stdin_doc) => Some(stdin_doc.clone()),
-                Err(DocumentError::Skipped { filename }) => {
-                    eprintln!("Skipped {:?}", filename);
-                    None
+    let wrap = match (
+        namespace,
+        namespace_template,
+        namespace_regex,
+        namespace_key,
+    ) {
+        (Some(namespace), ..) => Some(namespace),
+        (None, Some(template), ..) => Some(NamespaceWith::Template(template)),
+        (None, None, Some(regex), Some(template)) => Some(NamespaceWith::Regex { regex, template }),
+        _ => None,
+    };
+
+    // Where each input is moved to in the result: under its `into` path,
+    // and then under its namespace
+    let placement_of = |input: &InputSpec| {
+        let mut placement = input.into.clone().unwrap_or_default();
+        // Inline documents and commands have no name to make a key from
+        let wrapped_path = match (&input.source, input.is_stdin()) {
+            (Source::Inline(_), _) | (Source::Command(_), _) => None,
+            (Source::Archive { entry, .. }, _) => Some(entry),
+            (_, false) => Some(&input.path),
+            (_, true) => stdin_name.as_ref(),
+        };
+        match (&input.namespace, &wrap, wrapped_path) {
+            (Some(namespace), ..) => placement = placement.child(namespace.clone()),
+            (None, Some(using), Some(path)) => placement
+                .0
+                .extend(using.namespace(path, namespace_root.as_deref())?.0),
+            _ => {}
+        }
+        Ok::<_, String>(placement)
+    };
+    let inputs: Vec<(InputSpec, ValuePath)> = match inputs
+        .into_iter()
+        .map(|input| placement_of(&input).map(|placement| (input, placement)))
+        .collect()
+    {
+        Ok(inputs) => inputs,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            std::process::exit(1);
+        }
+    };
+
+    // Along with the text of each input, to find the lines of its values
+    type Placed = (InputSpec, ValuePath, Option<String>);
+    let (inputs, mut documents): (Vec<Placed>, Vec<(PathBuf, Document)>) = inputs
+        .into_iter()
+        .filter_map(|(input, placement)| {
+            // Check if is this is stdin's placeholder
+            let loaded = if input.is_stdin() {
+                // stdin's result is a singleton, but unfortunately most errors are
+                // not `Clone`, so we can't clone the entire result, which means
+                // we need to handle stdin's processing right here.
+                match stdin_doc_result.as_ref() {
+                    Ok(stdin_doc) => Ok((stdin_doc.clone(), Some(stdin_buffer.clone()))),
+                    Err(DocumentError::Skipped { filename }) => {
+                        eprintln!("Skipped {:?}", filename);
+                        return None;
+                    }
+                    Err(DocumentError::Loading { filename, error }) => {
+                        eprintln!("Error loading {:?}: {:?}", filename, error);
+                        return None;
+                    }
                 }
-                Err(DocumentError::Loading { filename, error }) => {
-                    eprintln!("Error loading {:?}: {:?}", filename, error);
-                    None
+            } else {
+                input.load()
+            };
+
+            match loaded {
+                // A command that fails would otherwise go unnoticed
+                Err(error @ DocumentError::Loading { .. })
+                    if matches!(input.source, Source::Command(_)) =>
+                {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
                 }
-            }
-        } else {
-            // Treat `filename` as a regular file
-            match filename
-                .extension()
-                .and_then(OsStr::to_str)
-                .map(|extension| {
-                    DocumentType::from_str(extension)
-                        .map_err(|_| DocumentError::Skipped {
-                            filename: filename.clone(),
-                        })
-                        .and_then(|doc_type| doc_type.load_from_path(&filename))
-                        .map(|doc| match wrap {
-                            Some(using) => using.wrap(doc, &filename),
-                            None => doc,
-                        })
-                }) {
-                Some(Err(DocumentError::Skipped { filename })) => {
+                Err(DocumentError::Skipped { filename }) => {
                     eprintln!("Skipped {:?}", filename);
                     None
                 }
-                Some(Err(DocumentError::Loading { filename, error })) => {
+                Err(DocumentError::Loading { filename, error }) => {
                     eprintln!("Error loading {:?}: {:?}", filename, error);
                     None
                 }
-                Some(Ok(document)) => Some(document),
-                None => {
-                    eprintln!("Skipped {:?}", filename);
-                    None
+                Ok((document, content)) => {
+                    let document = document.nested(&placement);
+                    let filename = input.path.clone();
+                    Some(((input, placement, content), (filename, document)))
                 }
             }
+        })
+        .unzip();
+
+    if let Some(Err(error)) = preset.map(|preset| preset.prepare(&mut documents)) {
+        eprintln!("Error merging {}", error);
+        std::process::exit(1);
+    }
+
+    let mut documents = inputs.into_iter().zip(documents);
+
+    let mut provenance = if explain || annotate {
+        Some(match preset {
+            Some(Preset::Helm) => Provenance::new(true),
+            Some(Preset::Openapi) | None => Provenance::new(false),
+        })
+    } else {
+        None
+    };
+
+    // Presets have their own rules for arrays
+    let array_merge_of = |input: &InputSpec| match preset {
+        Some(_) => ArrayMergeBehavior::Replace,
+        None => input.array_merge.unwrap_or(array_merge),
+    };
+
+    let mut trace = |destination: Option<&Document>,
+                     (input, placement, content): &Placed,
+                     document: &Document| {
+        if let Some(provenance) = provenance.as_mut() {
+            let mut lines = content.as_deref().map(line_map).unwrap_or_default();
+            if !placement.0.is_empty() {
+                lines = prefix_lines(lines, placement);
+            }
+            provenance.record(
+                &destination
+                    .cloned()
+                    .map(Document::into_yaml)
+                    .unwrap_or_default(),
+                &document.clone().into_yaml(),
+                &input.name(),
+                &lines,
+                array_merge_of(input),
+            );
         }
-    });
+    };
+
+    let mut report = if report || report_file.is_some() {
+        Some(MergeReport::default())
+    } else {
+        None
+    };
 
     let destination = force_format
         .as_ref()
         .map(|doc_type| doc_type.default_document())
         .unwrap_or_else(|| match documents.next() {
-            Some(loaded) => loaded,
+            Some((input, (_, loaded))) => {
+                trace(None, &input, &loaded);
+                if let Some(report) = report.as_mut() {
+                    report.record(&input.0.name(), &[], &loaded.clone().into_yaml());
+                }
+                loaded
+            }
             None => {
                 eprintln!("Got no documents to work with!");
                 std::process::exit(1);
             }
         });
 
-    let result = documents.fold(destination, |destination, document| {
-        destination.deep_merge(document, array_merge)
+    let result = documents.try_fold(destination, |destination, (input, (filename, document))| {
+        trace(Some(&destination), &input, &document);
+        let before = report.as_ref().map(|_| destination.clone().into_yaml());
+        let merged = match preset {
+            Some(preset) => preset.merge(destination, document, &filename)?,
+            None => destination.deep_merge(document, array_merge_of(&input.0)),
+        };
+        if let (Some(report), Some(before)) = (report.as_mut(), before) {
+            report.record(&input.0.name(), &before, &merged.clone().into_yaml());
+        }
+        Ok::<_, presets::PresetError>(merged)
     });
 
-    let stdout = io::stdout();
+    let result = match result {
+        Ok(result) => result,
+        Err(error) => {
+            eprintln!("Error merging {}", error);
+            std::process::exit(1);
+        }
+    };
 
-    match result {
-        Document::Json(json) => json.into_iter().map(|doc| doc.pretty(2)).for_each(|out| {
-            handle_stdout_error(writeln!(stdout.lock(), "{}", &out));
-        }),
-        Document::Yaml(yaml) => yaml
-            .into_iter()
-            .map(|doc| {
-                use yaml_rust as yamllib;
-                let mut out_str = String::new();
-                let mut emitter = yamllib::YamlEmitter::new(&mut out_str);
-                emitter.dump(&doc).unwrap();
-                out_str
+    let mut result = result;
+    for (_, flag, assignments) in overrides {
+        if let Some(provenance) = provenance.as_mut() {
+            for assignment in &assignments {
+                match assignment {
+                    Assignment::Set(path, value) if null_deletes && value.is_null() => {
+                        provenance.record_removal(path)
+                    }
+                    Assignment::Set(path, value) => provenance.record_value(path, value, flag),
+                    Assignment::Unset(path) => provenance.record_removal(path),
+                }
+            }
+        }
+        let before = report.as_ref().map(|_| result.clone().into_yaml());
+        result = assignments::apply(result, &assignments, null_deletes);
+        if let (Some(report), Some(before)) = (report.as_mut(), before) {
+            report.record(flag, &before, &result.clone().into_yaml());
+        }
+    }
+
+    if let Some(report) = report {
+        let rendered = report.render();
+        match &report_file {
+            Some(report_file) => {
+                if let Err(error) = std::fs::write(report_file, rendered) {
+                    eprintln!("Error writing {:?}: {}", report_file, error);
+                    std::process::exit(1);
+                }
+            }
+            None => eprint!("{}", rendered),
+        }
+    }
+
+    if let (Some(provenance), true) = (&provenance, explain) {
+        let yaml = result.into_yaml();
+        handle_stdout_error(write!(
+            io::stdout().lock(),
+            "{}",
+            render_explanation(&provenance.explain(&yaml), explain_format)
+        ));
+        return;
+    }
+
+    // Past `--explain`, provenance is only tracked for `--annotate`
+    let rendered_format = match (&provenance, output_format) {
+        (Some(_), _) => DocumentType::Yaml,
+        (None, Some(output_format)) => output_format,
+        (None, None) => result.doc_type(),
+    };
+    let rendered = match (&provenance, output_format) {
+        (Some(provenance), _) => result
+            .into_yaml()
+            .iter()
+            .enumerate()
+            .map(|(position, document)| {
+                let comment = |path: &ValuePath| {
+                    let origin = provenance.origin(position, path);
+                    let overridden = provenance.overridden(position, path);
+                    match (origin, overridden) {
+                        (Some(origin), Some(overridden)) => {
+                            Some(format!("from {}, overrides {}", origin, overridden))
+                        }
+                        (Some(origin), None) => Some(format!("from {}", origin)),
+                        (None, Some(overridden)) => Some(format!("overrides {}", overridden)),
+                        (None, None) => None,
+                    }
+                };
+                Ok(emitter::emit_with_comments(document, comment) + "\n")
             })
-            .for_each(|out| {
-                handle_stdout_error(writeln!(stdout.lock(), "{}", &out));
-            }),
+            .collect(),
+        (None, Some(output_format)) => {
+            Document::from_yaml(output_format, result.into_yaml()).render()
+        }
+        (None, None) => result.render(),
+    };
+    let rendered = match rendered {
+        Ok(rendered) => rendered,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            std::process::exit(1);
+        }
+    };
+
+    match output {
+        Some(output) if check => match stale(&output, &rendered, rendered_format, check_mode) {
+            Ok(None) => {}
+            Ok(Some(differences)) => {
+                eprintln!("{:?} is out of date:", output);
+                handle_stdout_error(write!(io::stdout().lock(), "{}", differences));
+                std::process::exit(1);
+            }
+            Err(error) => {
+                eprintln!("{}", error);
+                std::process::exit(2);
+            }
+        },
+        Some(output) => {
+            if let Some(suffix) = backup {
+                let mut backup = output.clone().into_os_string();
+                backup.push(suffix);
+                if let Err(error) = std::fs::copy(&output, &backup) {
+                    eprintln!("Error backing up {:?}: {}", output, error);
+                    std::process::exit(1);
+                }
+            }
+            if let Err(error) = write_atomically(&output, &rendered) {
+                eprintln!("Error writing {:?}: {}", output, error);
+                std::process::exit(1);
+            }
+        }
+        None => handle_stdout_error(write!(io::stdout().lock(), "{}", rendered)),
     }
 }
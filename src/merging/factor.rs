@@ -0,0 +1,137 @@
+use yaml_rust as yamllib;
+
+use crate::merging::ArrayMergeBehavior;
+
+/// The largest subtree shared by every value. Mappings keep the keys all
+/// values have (recursively), and with `Concat` arrays keep their common
+/// prefix, since an overlay can append the rest.
+fn common(values: &[&yamllib::Yaml], array_merge: ArrayMergeBehavior) -> Option<yamllib::Yaml> {
+    let (first, others) = values.split_first()?;
+    // Exact equality, so the overlays can reproduce every value (`1` and
+    // `"1"` are different keys)
+    if others.iter().all(|other| first == other) {
+        return Some((*first).clone());
+    }
+
+    match first {
+        yamllib::Yaml::Hash(first_hash) => {
+            let hashes: Vec<&yamllib::yaml::Hash> = others
+                .iter()
+                .map(|other| other.as_hash())
+                .collect::<Option<_>>()?;
+
+            let mut shared = yamllib::yaml::Hash::new();
+            for (key, first_value) in first_hash {
+                let mut children = vec![first_value];
+                for hash in &hashes {
+                    match hash.get(key) {
+                        Some(value) => children.push(value),
+                        None => break,
+                    }
+                }
+                if children.len() == values.len() {
+                    if let Some(value) = common(&children, array_merge) {
+                        shared.insert(key.clone(), value);
+                    }
+                }
+            }
+
+            if shared.is_empty() {
+                None
+            } else {
+                Some(yamllib::Yaml::Hash(shared))
+            }
+        }
+        yamllib::Yaml::Array(first_values) if array_merge == ArrayMergeBehavior::Concat => {
+            let arrays: Vec<&Vec<yamllib::Yaml>> = others
+                .iter()
+                .map(|other| other.as_vec())
+                .collect::<Option<_>>()?;
+
+            let prefix: Vec<yamllib::Yaml> = first_values
+                .iter()
+                .enumerate()
+                .take_while(|(index, value)| {
+                    arrays.iter().all(|array| {
+                        array
+                            .get(*index)
+                            .map(|other| *value == other)
+                            .unwrap_or(false)
+                    })
+                })
+                .map(|(_, value)| value.clone())
+                .collect();
+
+            if prefix.is_empty() {
+                None
+            } else {
+                Some(yamllib::Yaml::Array(prefix))
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Extracts the base every stream shares, document by document. Merging the
+/// matching overlay (see `unmerge`) onto it reproduces each input.
+pub(crate) fn factor(
+    streams: &[Vec<yamllib::Yaml>],
+    array_merge: ArrayMergeBehavior,
+) -> Vec<yamllib::Yaml> {
+    let documents = streams.iter().map(Vec::len).min().unwrap_or(0);
+    (0..documents)
+        .map(|position| {
+            let values: Vec<&yamllib::Yaml> =
+                streams.iter().map(|stream| &stream[position]).collect();
+            match common(&values, array_merge) {
+                Some(shared) => shared,
+                None if values.iter().all(|value| value.as_hash().is_some()) => {
+                    yamllib::Yaml::Hash(yamllib::yaml::Hash::new())
+                }
+                // Merging onto null simply takes the overlay
+                None => yamllib::Yaml::Null,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn load(source: &str) -> Vec<yamllib::Yaml> {
+        yamllib::YamlLoader::load_from_str(source).unwrap()
+    }
+
+    #[test_case(
+        &["{a: 1, b: {c: 1, d: 1}}", "{a: 1, b: {c: 1, d: 2}}", "{a: 1, b: {c: 1}, e: 3}"],
+        ArrayMergeBehavior::Replace,
+        "{a: 1, b: {c: 1}}"
+    )]
+    #[test_case(&["{a: 1}", "{a: 2}"], ArrayMergeBehavior::Replace, "{}"; "nothing shared")]
+    #[test_case(&["{a: 1}", "[1]"], ArrayMergeBehavior::Replace, "~"; "different types")]
+    #[test_case(&["{1: a, b: 1}", "{'1': a, b: 2}"], ArrayMergeBehavior::Replace, "{}"; "key types")]
+    #[test_case(
+        &["{'1': a, b: {c: 1}}", "{1: a, b: {c: 1}}"],
+        ArrayMergeBehavior::Replace,
+        "{b: {c: 1}}";
+        "key types with shared values"
+    )]
+    #[test_case(
+        &["{l: [1, 2, 3]}", "{l: [1, 2, 4]}"],
+        ArrayMergeBehavior::Concat,
+        "{l: [1, 2]}";
+        "array prefix"
+    )]
+    #[test_case(
+        &["{l: [1, 2, 3]}", "{l: [1, 2, 4]}"],
+        ArrayMergeBehavior::Replace,
+        "{}";
+        "arrays replace"
+    )]
+    fn test_factor(inputs: &[&str], array_merge: ArrayMergeBehavior, expected: &str) {
+        let streams: Vec<Vec<yamllib::Yaml>> = inputs.iter().map(|input| load(input)).collect();
+        assert_eq!(factor(&streams, array_merge), load(expected));
+    }
+}
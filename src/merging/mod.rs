@@ -1,9 +1,16 @@
+mod factor;
 mod json;
+mod three_way;
+mod unmerge;
 mod yaml;
 
 use strum_macros::{Display, EnumString, EnumVariantNames};
 
-#[derive(Debug, Copy, Clone, Display, EnumString, EnumVariantNames)]
+pub(crate) use factor::factor;
+pub(crate) use three_way::{merge3, Conflict, ConflictMarkers, ConflictStyle};
+pub(crate) use unmerge::unmerge;
+
+#[derive(Debug, Copy, Clone, PartialEq, Display, EnumString, EnumVariantNames)]
 #[strum(serialize_all = "kebab-case")]
 pub(crate) enum ArrayMergeBehavior {
     Replace,
@@ -0,0 +1,261 @@
+use strum_macros::{Display, EnumString, EnumVariantNames};
+use yaml_rust as yamllib;
+
+use crate::paths::ValuePath;
+
+/// What to do with values that both sides changed in different ways.
+#[derive(Debug, Clone, Copy, Default, Display, EnumString, EnumVariantNames)]
+#[strum(serialize_all = "kebab-case")]
+pub(crate) enum ConflictStyle {
+    /// Keep our side and report the conflicting paths.
+    #[default]
+    Report,
+    /// Replace the conflicting value with a mapping holding every side,
+    /// keyed by conflict markers.
+    Markers,
+}
+
+/// Labels used to build the conflict marker keys, like `<<<<<<< ours`.
+#[derive(Debug, Clone)]
+pub(crate) struct ConflictMarkers {
+    pub ours: String,
+    pub base: String,
+    pub theirs: String,
+}
+
+impl Default for ConflictMarkers {
+    fn default() -> Self {
+        Self {
+            ours: "ours".into(),
+            base: "base".into(),
+            theirs: "theirs".into(),
+        }
+    }
+}
+
+/// A value both sides changed in different ways. A side set to `None` means
+/// the value doesn't exist there (for example, because it was deleted).
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Conflict {
+    pub path: ValuePath,
+    pub base: Option<yamllib::Yaml>,
+    pub ours: Option<yamllib::Yaml>,
+    pub theirs: Option<yamllib::Yaml>,
+}
+
+struct ThreeWayMerge<'a> {
+    style: ConflictStyle,
+    markers: &'a ConflictMarkers,
+    conflicts: Vec<Conflict>,
+}
+
+impl<'a> ThreeWayMerge<'a> {
+    fn conflict(
+        &mut self,
+        path: &ValuePath,
+        base: Option<&yamllib::Yaml>,
+        ours: Option<yamllib::Yaml>,
+        theirs: Option<yamllib::Yaml>,
+    ) -> Option<yamllib::Yaml> {
+        let conflict = Conflict {
+            path: path.clone(),
+            base: base.cloned(),
+            ours,
+            theirs,
+        };
+
+        let resolved = match self.style {
+            ConflictStyle::Report => conflict.ours.clone(),
+            ConflictStyle::Markers => {
+                let mut marker = yamllib::yaml::Hash::new();
+                let sides = [
+                    (format!("<<<<<<< {}", self.markers.ours), &conflict.ours),
+                    (format!("||||||| {}", self.markers.base), &conflict.base),
+                    (format!(">>>>>>> {}", self.markers.theirs), &conflict.theirs),
+                ];
+                for (label, side) in sides.iter() {
+                    if let Some(value) = side {
+                        marker.insert(yamllib::Yaml::String(label.clone()), value.clone());
+                    }
+                }
+                Some(yamllib::Yaml::Hash(marker))
+            }
+        };
+
+        self.conflicts.push(conflict);
+        resolved
+    }
+
+    fn merge(
+        &mut self,
+        path: &ValuePath,
+        base: Option<&yamllib::Yaml>,
+        ours: Option<yamllib::Yaml>,
+        theirs: Option<yamllib::Yaml>,
+    ) -> Option<yamllib::Yaml> {
+        if ours == theirs || theirs.as_ref() == base {
+            return ours;
+        }
+        if ours.as_ref() == base {
+            return theirs;
+        }
+
+        match (ours, theirs) {
+            (Some(yamllib::Yaml::Hash(ours_hash)), Some(yamllib::Yaml::Hash(mut theirs_hash))) => {
+                let empty = yamllib::yaml::Hash::new();
+                let base_hash = match base {
+                    Some(yamllib::Yaml::Hash(base_hash)) => base_hash,
+                    _ => &empty,
+                };
+
+                let mut merged = yamllib::yaml::Hash::new();
+                for (key, ours_value) in ours_hash {
+                    let theirs_value = theirs_hash.remove(&key);
+                    if let Some(value) = self.merge(
+                        &path.child_key(&key),
+                        base_hash.get(&key),
+                        Some(ours_value),
+                        theirs_value,
+                    ) {
+                        merged.insert(key, value);
+                    }
+                }
+                for (key, theirs_value) in theirs_hash {
+                    if let Some(value) = self.merge(
+                        &path.child_key(&key),
+                        base_hash.get(&key),
+                        None,
+                        Some(theirs_value),
+                    ) {
+                        merged.insert(key, value);
+                    }
+                }
+                Some(yamllib::Yaml::Hash(merged))
+            }
+            (
+                Some(yamllib::Yaml::Array(ours_values)),
+                Some(yamllib::Yaml::Array(theirs_values)),
+            ) if ours_values.len() == theirs_values.len()
+                && base.and_then(yamllib::Yaml::as_vec).map(Vec::len)
+                    == Some(ours_values.len()) =>
+            {
+                // Same shape everywhere, so we can look at each element
+                // on its own.
+                let base_values = base.and_then(yamllib::Yaml::as_vec);
+                Some(yamllib::Yaml::Array(
+                    ours_values
+                        .into_iter()
+                        .zip(theirs_values)
+                        .enumerate()
+                        .filter_map(|(index, (ours_value, theirs_value))| {
+                            self.merge(
+                                &path.index(index),
+                                base_values.and_then(|values| values.get(index)),
+                                Some(ours_value),
+                                Some(theirs_value),
+                            )
+                        })
+                        .collect(),
+                ))
+            }
+            (ours, theirs) => self.conflict(path, base, ours, theirs),
+        }
+    }
+}
+
+/// Merges the changes `ours` and `theirs` made to their common ancestor
+/// `base`. Returns the merged streams along with the conflicts found (which,
+/// depending on `style`, may have been embedded in the result).
+pub(crate) fn merge3(
+    base: Vec<yamllib::Yaml>,
+    ours: Vec<yamllib::Yaml>,
+    theirs: Vec<yamllib::Yaml>,
+    style: ConflictStyle,
+    markers: &ConflictMarkers,
+) -> (Vec<yamllib::Yaml>, Vec<Conflict>) {
+    let mut merge = ThreeWayMerge {
+        style,
+        markers,
+        conflicts: Vec::new(),
+    };
+
+    let count = base.len().max(ours.len()).max(theirs.len());
+    let mut base = base.into_iter();
+    let mut ours = ours.into_iter();
+    let mut theirs = theirs.into_iter();
+
+    let merged = (0..count)
+        .filter_map(|_| {
+            let base = base.next();
+            merge.merge(
+                &ValuePath::default(),
+                base.as_ref(),
+                ours.next(),
+                theirs.next(),
+            )
+        })
+        .collect();
+
+    (merged, merge.conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn load(source: &str) -> Vec<yamllib::Yaml> {
+        yamllib::YamlLoader::load_from_str(source).unwrap()
+    }
+
+    #[test_case("{a: 1, b: 1}", "{a: 2, b: 1}", "{a: 1, b: 2}", "{a: 2, b: 2}"; "both sides")]
+    #[test_case("{a: 1, b: 1}", "{a: 2, b: 1}", "{a: 2, b: 1}", "{a: 2, b: 1}"; "same change")]
+    #[test_case("{a: 1, b: 1}", "{b: 1}", "{a: 1, b: 2}", "{b: 2}"; "deletion")]
+    #[test_case("{a: 1}", "{a: 1, b: {x: 1}}", "{a: 1, c: 3}", "{a: 1, b: {x: 1}, c: 3}"; "additions")]
+    #[test_case("{l: [1, 2, 3]}", "{l: [0, 2, 3]}", "{l: [1, 2, 4]}", "{l: [0, 2, 4]}"; "arrays")]
+    fn test_merge3_clean(base: &str, ours: &str, theirs: &str, expected: &str) {
+        let (merged, conflicts) = merge3(
+            load(base),
+            load(ours),
+            load(theirs),
+            ConflictStyle::Report,
+            &ConflictMarkers::default(),
+        );
+        assert_eq!(conflicts, vec![]);
+        assert_eq!(merged, load(expected));
+    }
+
+    #[test_case(
+        "{a: 1}",
+        "{a: 2}",
+        "{a: 3}",
+        ConflictStyle::Report,
+        "{a: 2}";
+        "report keeps ours"
+    )]
+    #[test_case(
+        "{a: {b: 1}}",
+        "{a: {b: 2}}",
+        "{a: {}}",
+        ConflictStyle::Markers,
+        "{a: {b: {'<<<<<<< ours': 2, '||||||| base': 1}}}";
+        "markers"
+    )]
+    fn test_merge3_conflicts(
+        base: &str,
+        ours: &str,
+        theirs: &str,
+        style: ConflictStyle,
+        expected: &str,
+    ) {
+        let (merged, conflicts) = merge3(
+            load(base),
+            load(ours),
+            load(theirs),
+            style,
+            &ConflictMarkers::default(),
+        );
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(merged, load(expected));
+    }
+}
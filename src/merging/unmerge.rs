@@ -0,0 +1,205 @@
+use itertools::{EitherOrBoth, Itertools};
+use yaml_rust as yamllib;
+
+use crate::merging::ArrayMergeBehavior;
+use crate::paths::ValuePath;
+
+/// A difference between the base and the target that no overlay can
+/// produce, like a key that has to disappear when merging can't delete.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Unrepresentable {
+    pub path: ValuePath,
+    pub reason: &'static str,
+}
+
+struct Unmerge {
+    array_merge: ArrayMergeBehavior,
+    null_deletes: bool,
+    problems: Vec<Unrepresentable>,
+}
+
+impl Unmerge {
+    fn problem(&mut self, path: ValuePath, reason: &'static str) -> Option<yamllib::Yaml> {
+        self.problems.push(Unrepresentable { path, reason });
+        None
+    }
+
+    /// Returns the overlay that turns `base` into `target`, or `None` when
+    /// nothing needs to change.
+    fn overlay(
+        &mut self,
+        path: ValuePath,
+        base: &yamllib::Yaml,
+        target: &yamllib::Yaml,
+    ) -> Option<yamllib::Yaml> {
+        // Exact equality: values a reader would take as the same (`1` and
+        // `1.0`, or keys `1` and `"1"`) don't merge into the same document
+        if base == target {
+            return None;
+        }
+
+        match (base, target) {
+            (yamllib::Yaml::Hash(base_hash), yamllib::Yaml::Hash(target_hash)) => {
+                let mut overlay = yamllib::yaml::Hash::new();
+                for (key, target_value) in target_hash {
+                    let value = match base_hash.get(key) {
+                        Some(base_value) => {
+                            self.overlay(path.child_key(key), base_value, target_value)
+                        }
+                        None if self.null_deletes
+                            && matches!(target_value, yamllib::Yaml::Null) =>
+                        {
+                            self.problem(path.child_key(key), "a null value would delete the key")
+                        }
+                        None => Some(target_value.clone()),
+                    };
+                    if let Some(value) = value {
+                        overlay.insert(key.clone(), value);
+                    }
+                }
+
+                for base_key in base_hash.keys() {
+                    if !target_hash.contains_key(base_key) {
+                        if self.null_deletes {
+                            overlay.insert(base_key.clone(), yamllib::Yaml::Null);
+                        } else {
+                            self.problem(path.child_key(base_key), "merging can't remove keys");
+                        }
+                    }
+                }
+
+                if overlay.is_empty() {
+                    None
+                } else {
+                    Some(yamllib::Yaml::Hash(overlay))
+                }
+            }
+            (yamllib::Yaml::Array(base_values), yamllib::Yaml::Array(target_values))
+                if self.array_merge == ArrayMergeBehavior::Concat =>
+            {
+                let is_prefix = base_values.len() <= target_values.len()
+                    && base_values
+                        .iter()
+                        .zip(target_values)
+                        .all(|(base, target)| base == target);
+                if is_prefix {
+                    Some(yamllib::Yaml::Array(
+                        target_values[base_values.len()..].to_vec(),
+                    ))
+                } else {
+                    self.problem(path, "concatenating can't change existing elements")
+                }
+            }
+            (_, yamllib::Yaml::Null) if self.null_deletes => {
+                self.problem(path, "a null value would delete the key")
+            }
+            (_, target) => Some(target.clone()),
+        }
+    }
+}
+
+/// Computes the smallest overlay that, merged onto `base` with `array_merge`,
+/// reproduces `target`. With `null_deletes` (like Helm does) a `null` in the
+/// overlay deletes keys, so removals can be represented too.
+pub(crate) fn unmerge(
+    base: &[yamllib::Yaml],
+    target: &[yamllib::Yaml],
+    array_merge: ArrayMergeBehavior,
+    null_deletes: bool,
+) -> Result<Vec<yamllib::Yaml>, Vec<Unrepresentable>> {
+    let mut unmerge = Unmerge {
+        array_merge,
+        null_deletes,
+        problems: Vec::new(),
+    };
+
+    let overlay = base
+        .iter()
+        .zip_longest(target)
+        .filter_map(|zipped| match zipped {
+            EitherOrBoth::Both(base, target) => {
+                Some(match unmerge.overlay(ValuePath::default(), base, target) {
+                    Some(overlay) => overlay,
+                    // Merging an empty mapping keeps the base as it is
+                    None if base.as_hash().is_some() => {
+                        yamllib::Yaml::Hash(yamllib::yaml::Hash::new())
+                    }
+                    // And so does concatenating an empty array
+                    None if base.as_vec().is_some()
+                        && array_merge == ArrayMergeBehavior::Concat =>
+                    {
+                        yamllib::Yaml::Array(Vec::new())
+                    }
+                    None => target.clone(),
+                })
+            }
+            EitherOrBoth::Left(_) => {
+                unmerge.problem(ValuePath::default(), "merging can't remove documents")
+            }
+            EitherOrBoth::Right(target) => Some(target.clone()),
+        })
+        .collect();
+
+    if unmerge.problems.is_empty() {
+        Ok(overlay)
+    } else {
+        Err(unmerge.problems)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conversions::YamlValue;
+    use crate::diff::same_value;
+    use crate::merging::DeepMerge;
+    use test_case::test_case;
+
+    fn load(source: &str) -> Vec<yamllib::Yaml> {
+        yamllib::YamlLoader::load_from_str(source).unwrap()
+    }
+
+    #[test_case(
+        "{a: 1, b: {c: 2, d: 3}}",
+        "{a: 1, b: {c: 2, d: 4}}",
+        ArrayMergeBehavior::Replace,
+        "{b: {d: 4}}"
+    )]
+    #[test_case("{a: 1}", "{a: 1}", ArrayMergeBehavior::Replace, "{}"; "nothing to do")]
+    #[test_case("{a: 1}", "{a: 1, b: [1]}", ArrayMergeBehavior::Replace, "{b: [1]}"; "new keys")]
+    #[test_case("{l: [1, 2]}", "{l: [1, 2, 3]}", ArrayMergeBehavior::Concat, "{l: [3]}"; "concat")]
+    #[test_case("{l: [1, 2]}", "{l: [1, 2, 3]}", ArrayMergeBehavior::Replace, "{l: [1, 2, 3]}"; "replace")]
+    #[test_case("{a: {b: 1}}", "{a: 2}", ArrayMergeBehavior::Replace, "{a: 2}"; "type change")]
+    #[test_case("{a: 1, b: {c: 2}}", "{b: {c: 2}, a: 1}", ArrayMergeBehavior::Replace, "{}"; "key order")]
+    #[test_case("[1, 2]", "[1, 2]", ArrayMergeBehavior::Concat, "[]"; "unchanged root array")]
+    fn test_unmerge(base: &str, target: &str, array_merge: ArrayMergeBehavior, expected: &str) {
+        let overlay = unmerge(&load(base), &load(target), array_merge, false).unwrap();
+        assert_eq!(overlay, load(expected));
+
+        let merged = YamlValue(load(base).remove(0))
+            .deep_merge(YamlValue(overlay[0].clone()), array_merge)
+            .0;
+        assert!(same_value(&merged, &load(target)[0]));
+    }
+
+    #[test_case("{a: 1, b: 2}", "{a: 1}", ArrayMergeBehavior::Replace; "removed key")]
+    #[test_case("{l: [1, 2]}", "{l: [2, 3]}", ArrayMergeBehavior::Concat; "concat prefix")]
+    #[test_case("{'1': a, b: 1}", "{1: a, b: 2}", ArrayMergeBehavior::Replace; "key types")]
+    #[test_case("{'1': a}", "{1: a}", ArrayMergeBehavior::Replace; "only key types")]
+    fn test_unrepresentable(base: &str, target: &str, array_merge: ArrayMergeBehavior) {
+        assert!(unmerge(&load(base), &load(target), array_merge, false).is_err());
+    }
+
+    #[test]
+    fn test_null_deletes() {
+        assert_eq!(
+            unmerge(
+                &load("{a: 1, b: 2}"),
+                &load("{a: 1}"),
+                ArrayMergeBehavior::Replace,
+                true
+            ),
+            Ok(load("{b: ~}"))
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use regex::Regex;
+
+use crate::paths::{PathSegment, ValuePath};
+
+/// How the key a file is wrapped with is made out of its path.
+#[derive(Debug, Clone)]
+pub(crate) enum NamespaceWith {
+    /// A key rendered from a template of the parts of the path, like
+    /// `{parent}_{stem}`.
+    Template(String),
+    /// Like `Template`, where the groups of `regex` (matched against the
+    /// file name) can be used as placeholders too.
+    Regex { regex: Regex, template: String },
+    /// Nested keys, one per directory and the stem of the file name.
+    Tree,
+}
+
+impl FromStr for NamespaceWith {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "path" => Ok(NamespaceWith::Template("{path}".into())),
+            "filename" => Ok(NamespaceWith::Template("{filename}".into())),
+            "basename" => Ok(NamespaceWith::Template("{stem}".into())),
+            "tree" => Ok(NamespaceWith::Tree),
+            _ => Err("Invalid wrap with option"),
+        }
+    }
+}
+
+/// Drops the `.` components, so `./conf/a.yaml` and `conf/a.yaml` are the
+/// same path.
+fn without_cur_dir(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| component != &Component::CurDir)
+        .collect()
+}
+
+fn lossy(name: Option<&OsStr>) -> String {
+    name.map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Replaces every `{name}` in `template` with what `lookup` returns for it.
+fn render<F>(template: &str, lookup: F) -> Result<String, String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("Unclosed placeholder in {:?}", template))?;
+        let name = &rest[start + 1..start + end];
+        rendered.push_str(
+            &lookup(name)
+                .ok_or_else(|| format!("Unknown placeholder {{{}}} in {:?}", name, template))?,
+        );
+        rest = &rest[start + end + 1..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+impl NamespaceWith {
+    /// Where a document read from `path` is wrapped: under a single key,
+    /// except for `Tree`, which nests it following its directories. `root`
+    /// is stripped from `path` first (when it's under it, `./` aside), for
+    /// the keys that use the directories.
+    pub(crate) fn namespace<P: AsRef<Path>>(
+        &self,
+        path: P,
+        root: Option<&Path>,
+    ) -> Result<ValuePath, String> {
+        let path = path.as_ref();
+        let stripped = root.and_then(|root| {
+            without_cur_dir(path)
+                .strip_prefix(without_cur_dir(root))
+                .ok()
+                .map(PathBuf::from)
+        });
+        let relative = stripped.as_deref().unwrap_or(path);
+
+        let parts = |name: &str| match name {
+            "path" => Some(relative.to_string_lossy().into_owned()),
+            "dir" => Some(lossy(relative.parent().map(Path::as_os_str))),
+            "parent" => Some(lossy(path.parent().and_then(Path::file_name))),
+            "filename" => Some(lossy(path.file_name())),
+            "stem" => Some(lossy(path.file_stem())),
+            "ext" => Some(lossy(path.extension())),
+            _ => None,
+        };
+
+        let key = match self {
+            NamespaceWith::Template(template) => render(template, parts)?,
+            NamespaceWith::Regex { regex, template } => {
+                let filename = lossy(path.file_name());
+                let captures = regex.captures(&filename).ok_or_else(|| {
+                    format!(
+                        "{:?} doesn't match the namespace regex {:?}",
+                        filename,
+                        regex.as_str()
+                    )
+                })?;
+                render(template, |name| {
+                    let group = match name.parse::<usize>() {
+                        Ok(index) => captures.get(index),
+                        Err(_) => captures.name(name),
+                    };
+                    group
+                        .map(|group| group.as_str().to_owned())
+                        .or_else(|| parts(name))
+                })?
+            }
+            NamespaceWith::Tree => {
+                let mut names: Vec<&OsStr> = relative
+                    .components()
+                    .filter_map(|component| match component {
+                        Component::Normal(name) => Some(name),
+                        _ => None,
+                    })
+                    .collect();
+                if let Some(last) = names.pop() {
+                    names.push(Path::new(last).file_stem().unwrap_or(last));
+                }
+                return Ok(ValuePath(
+                    names
+                        .into_iter()
+                        .map(|name| PathSegment::Key(name.to_string_lossy().into()))
+                        .collect(),
+                ));
+            }
+        };
+        Ok(ValuePath::default().child(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn matching(regex: &str, template: &str) -> NamespaceWith {
+        NamespaceWith::Regex {
+            regex: Regex::new(regex).unwrap(),
+            template: template.into(),
+        }
+    }
+
+    #[test_case("path".parse().unwrap(), "conf/db/primary.yaml", None, &["conf/db/primary.yaml"])]
+    #[test_case("path".parse().unwrap(), "./conf/db/primary.yaml", None, &["./conf/db/primary.yaml"]; "path kept")]
+    #[test_case("path".parse().unwrap(), "./conf/db/primary.yaml", Some("conf"), &["db/primary.yaml"]; "path root")]
+    #[test_case("filename".parse().unwrap(), "conf/db/primary.yaml", None, &["primary.yaml"])]
+    #[test_case("basename".parse().unwrap(), "conf/db/primary.yaml", None, &["primary"])]
+    #[test_case(NamespaceWith::Tree, "./conf/db/primary.yaml", None, &["conf", "db", "primary"])]
+    #[test_case(NamespaceWith::Tree, "conf/db/primary.yaml", Some("./conf/"), &["db", "primary"]; "tree root")]
+    #[test_case(NamespaceWith::Tree, "/etc/conf/a.b.json", Some("/etc"), &["conf", "a.b"]; "tree absolute")]
+    #[test_case(NamespaceWith::Template("{parent}_{stem}".into()), "conf/db/primary.yaml", None, &["db_primary"]; "template")]
+    #[test_case(matching(r"^(?P<env>\w+)\.values\.yaml$", "{env}"), "envs/prod.values.yaml", None, &["prod"]; "regex")]
+    #[test_case(matching(r"^(\w+)\.", "{parent}-{1}"), "envs/prod.values.yaml", None, &["envs-prod"]; "regex numbered")]
+    fn test_namespace(using: NamespaceWith, path: &str, root: Option<&str>, expected: &[&str]) {
+        let expected = ValuePath(
+            expected
+                .iter()
+                .map(|key| PathSegment::Key(key.to_string()))
+                .collect(),
+        );
+        assert_eq!(using.namespace(path, root.map(Path::new)), Ok(expected));
+    }
+
+    #[test_case(NamespaceWith::Template("{nope}".into()); "unknown placeholder")]
+    #[test_case(NamespaceWith::Template("{stem".into()); "unclosed placeholder")]
+    #[test_case(matching(r"^(?P<env>\w+)\.values\.yaml$", "{env}"); "no match")]
+    fn test_namespace_errors(using: NamespaceWith) {
+        assert!(using.namespace("conf/values.yaml", None).is_err());
+    }
+}
@@ -0,0 +1,374 @@
+use std::fmt;
+use std::str::FromStr;
+
+use yaml_rust as yamllib;
+
+/// A single step when walking into a value: either a mapping key or an array
+/// index.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// The largest array index a path can have (like Helm's), so setting a value
+/// can't make an array grow without bounds.
+const MAX_INDEX: usize = 65536;
+
+/// A location inside a document, written like `a.b[0].c`. Dots, brackets and
+/// backslashes that are part of a key can be escaped with a backslash
+/// (`a\.b` is the single key `a.b`). The empty string is the root.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct ValuePath(pub Vec<PathSegment>);
+
+impl FromStr for ValuePath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut segments = Vec::new();
+        let mut key = String::new();
+        // Whether `key` holds a segment that still has to be pushed
+        let mut pending = false;
+        // Whether the last thing we saw was a dot, so a key must follow
+        let mut after_dot = false;
+        // Whether the last thing we saw was an index, so a key can't follow
+        let mut after_index = false;
+        let mut chars = s.chars();
+
+        while let Some(c) = chars.next() {
+            if after_index && !matches!(c, '.' | '[' | ']') {
+                return Err(format!("Expected `.` or `[` after `]` in path {:?}", s));
+            }
+            match c {
+                '\\' => match chars.next() {
+                    Some(escaped) => {
+                        key.push(escaped);
+                        pending = true;
+                    }
+                    None => return Err(format!("Dangling escape at the end of path {:?}", s)),
+                },
+                '.' => {
+                    if pending {
+                        segments.push(PathSegment::Key(std::mem::take(&mut key)));
+                        pending = false;
+                    } else if after_dot || segments.is_empty() {
+                        return Err(format!("Empty key in path {:?}", s));
+                    }
+                    after_dot = true;
+                    after_index = false;
+                }
+                '[' => {
+                    if pending {
+                        segments.push(PathSegment::Key(std::mem::take(&mut key)));
+                        pending = false;
+                    } else if after_dot {
+                        return Err(format!("Empty key in path {:?}", s));
+                    }
+                    let mut index = String::new();
+                    loop {
+                        match chars.next() {
+                            Some(']') => break,
+                            Some(c) => index.push(c),
+                            None => return Err(format!("Unbalanced `[` in path {:?}", s)),
+                        }
+                    }
+                    let index: usize = index
+                        .trim()
+                        .parse()
+                        .map_err(|_| format!("Invalid array index {:?} in path {:?}", index, s))?;
+                    if index > MAX_INDEX {
+                        return Err(format!(
+                            "Array index {} in path {:?} is too large (at most {})",
+                            index, s, MAX_INDEX
+                        ));
+                    }
+                    segments.push(PathSegment::Index(index));
+                    after_dot = false;
+                    after_index = true;
+                }
+                ']' => return Err(format!("Unbalanced `]` in path {:?}", s)),
+                c => {
+                    key.push(c);
+                    pending = true;
+                    after_dot = false;
+                }
+            }
+        }
+
+        if pending {
+            segments.push(PathSegment::Key(key));
+        } else if after_dot {
+            return Err(format!("Empty key in path {:?}", s));
+        }
+
+        Ok(ValuePath(segments))
+    }
+}
+
+impl fmt::Display for ValuePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (position, segment) in self.0.iter().enumerate() {
+            match segment {
+                PathSegment::Key(key) => {
+                    if position > 0 {
+                        f.write_str(".")?;
+                    }
+                    for c in key.chars() {
+                        if matches!(c, '.' | '[' | ']' | '\\') {
+                            f.write_str("\\")?;
+                        }
+                        write!(f, "{}", c)?;
+                    }
+                }
+                PathSegment::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The textual form of a mapping key, as used in paths. Keys that differ only
+/// in their type (like `1` and `"1"`) end up the same.
+pub(crate) fn key_string(key: &yamllib::Yaml) -> String {
+    match key {
+        yamllib::Yaml::String(value) | yamllib::Yaml::Real(value) => value.clone(),
+        yamllib::Yaml::Integer(value) => value.to_string(),
+        yamllib::Yaml::Boolean(value) => value.to_string(),
+        yamllib::Yaml::Null => "~".into(),
+        other => format!("{:?}", other),
+    }
+}
+
+/// Looks up the entry for `key` in a YAML mapping. Besides string keys, this
+/// also matches keys that were parsed as numbers or booleans (so `ports.80`
+/// finds `{80: ...}`).
+fn key_matches(candidate: &yamllib::Yaml, key: &str) -> bool {
+    match candidate {
+        yamllib::Yaml::String(value) | yamllib::Yaml::Real(value) => value == key,
+        yamllib::Yaml::Integer(value) => value.to_string() == key,
+        yamllib::Yaml::Boolean(value) => value.to_string() == key,
+        _ => false,
+    }
+}
+
+fn find_key(hash: &yamllib::yaml::Hash, key: &str) -> Option<yamllib::Yaml> {
+    hash.keys()
+        .find(|candidate| key_matches(candidate, key))
+        .cloned()
+}
+
+impl ValuePath {
+    pub fn child<S: Into<String>>(&self, key: S) -> Self {
+        let mut path = self.clone();
+        path.0.push(PathSegment::Key(key.into()));
+        path
+    }
+
+    /// Like `child`, but for keys taken from a YAML mapping.
+    pub fn child_key(&self, key: &yamllib::Yaml) -> Self {
+        self.child(key_string(key))
+    }
+
+    /// Like `Display`, but makes the root visible.
+    pub fn describe(&self) -> String {
+        if self.0.is_empty() {
+            "(root)".into()
+        } else {
+            self.to_string()
+        }
+    }
+
+    pub fn index(&self, index: usize) -> Self {
+        let mut path = self.clone();
+        path.0.push(PathSegment::Index(index));
+        path
+    }
+
+    /// The value at this path, if there is one.
+    pub fn get<'a>(&self, root: &'a yamllib::Yaml) -> Option<&'a yamllib::Yaml> {
+        let mut current = root;
+        for segment in &self.0 {
+            current = match (segment, current) {
+                (PathSegment::Key(key), yamllib::Yaml::Hash(hash)) => {
+                    hash.get(&find_key(hash, key)?)?
+                }
+                (PathSegment::Index(index), yamllib::Yaml::Array(values)) => values.get(*index)?,
+                _ => return None,
+            };
+        }
+        Some(current)
+    }
+
+    /// Stores `value` at this path, creating any missing intermediate node
+    /// along the way: mappings for keys and arrays (padded with nulls) for
+    /// indices. Existing nodes of the wrong kind are replaced.
+    pub fn set(&self, root: &mut yamllib::Yaml, value: yamllib::Yaml) {
+        let mut current = root;
+        for segment in &self.0 {
+            current = match segment {
+                PathSegment::Key(key) => {
+                    if !matches!(current, yamllib::Yaml::Hash(_)) {
+                        *current = yamllib::Yaml::Hash(yamllib::yaml::Hash::new());
+                    }
+                    match current {
+                        yamllib::Yaml::Hash(hash) => {
+                            let found = find_key(hash, key)
+                                .unwrap_or_else(|| yamllib::Yaml::String(key.clone()));
+                            hash.entry(found).or_insert(yamllib::Yaml::Null)
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+                PathSegment::Index(index) => {
+                    if !matches!(current, yamllib::Yaml::Array(_)) {
+                        *current = yamllib::Yaml::Array(Vec::new());
+                    }
+                    match current {
+                        yamllib::Yaml::Array(values) => {
+                            if values.len() <= *index {
+                                // Parsed paths have their indices capped, so
+                                // this can't overflow
+                                let length = index.checked_add(1).expect("array index overflow");
+                                values.resize(length, yamllib::Yaml::Null);
+                            }
+                            &mut values[*index]
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+            };
+        }
+        *current = value;
+    }
+
+    /// Removes the value at this path, returning it if it was present.
+    /// Removing the root resets the document to null.
+    pub fn remove(&self, root: &mut yamllib::Yaml) -> Option<yamllib::Yaml> {
+        let (last, parents) = match self.0.split_last() {
+            Some(split) => split,
+            None => return Some(std::mem::replace(root, yamllib::Yaml::Null)),
+        };
+
+        let mut current = root;
+        for segment in parents {
+            current = match (segment, current) {
+                (PathSegment::Key(key), yamllib::Yaml::Hash(hash)) => {
+                    let found = find_key(hash, key)?;
+                    hash.get_mut(&found)?
+                }
+                (PathSegment::Index(index), yamllib::Yaml::Array(values)) => {
+                    values.get_mut(*index)?
+                }
+                _ => return None,
+            };
+        }
+
+        match (last, current) {
+            (PathSegment::Key(key), yamllib::Yaml::Hash(hash)) => {
+                let found = find_key(hash, key)?;
+                hash.remove(&found)
+            }
+            (PathSegment::Index(index), yamllib::Yaml::Array(values)) if *index < values.len() => {
+                Some(values.remove(*index))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("", &[]; "root")]
+    #[test_case("a", &[PathSegment::Key("a".into())])]
+    #[test_case(
+        "a.b[0].c",
+        &[
+            PathSegment::Key("a".into()),
+            PathSegment::Key("b".into()),
+            PathSegment::Index(0),
+            PathSegment::Key("c".into()),
+        ]
+    )]
+    #[test_case(
+        "[2][3]",
+        &[PathSegment::Index(2), PathSegment::Index(3)];
+        "nested indices"
+    )]
+    #[test_case(
+        r"a\.b.c",
+        &[PathSegment::Key("a.b".into()), PathSegment::Key("c".into())];
+        "escaped dot"
+    )]
+    fn test_parse_path(input: &str, expected: &[PathSegment]) {
+        let parsed: ValuePath = input.parse().unwrap();
+        assert_eq!(parsed.0, expected);
+        assert_eq!(parsed.to_string(), input);
+    }
+
+    #[test_case("a..b"; "empty key")]
+    #[test_case("a."; "trailing dot")]
+    #[test_case(".a"; "leading dot")]
+    #[test_case("a[x]"; "invalid index")]
+    #[test_case("a[65537]"; "index too large")]
+    #[test_case("a[18446744073709551615]"; "largest index")]
+    #[test_case("a]"; "unbalanced bracket")]
+    #[test_case("a[1"; "unclosed bracket")]
+    #[test_case("a[0]b"; "key after index")]
+    #[test_case(r"a[0]\.b"; "escaped key after index")]
+    fn test_parse_invalid_path(input: &str) {
+        assert!(input.parse::<ValuePath>().is_err());
+    }
+
+    #[test_case("a: 1", "b.c", "x", "{a: 1, b: {c: x}}")]
+    #[test_case("a: 1", "a", "x", "{a: x}")]
+    #[test_case("a: 1", "a[1].b", "x", "{a: [~, {b: x}]}")]
+    #[test_case("{a: [1, 2, 3]}", "a[1]", "x", "{a: [1, x, 3]}")]
+    #[test_case("{80: http}", "80", "web", "{80: web}"; "non string keys")]
+    fn test_set_path(current: &str, path: &str, value: &str, expected: &str) {
+        let mut current = yamllib::YamlLoader::load_from_str(current)
+            .unwrap()
+            .remove(0);
+        let value = yamllib::YamlLoader::load_from_str(value).unwrap().remove(0);
+        path.parse::<ValuePath>().unwrap().set(&mut current, value);
+        assert_eq!(
+            current,
+            yamllib::YamlLoader::load_from_str(expected).unwrap()[0]
+        );
+    }
+
+    #[test_case("{a: {b: [1, {c: 2}]}}", "a.b[1].c", Some("2"))]
+    #[test_case("{a: 1}", "", Some("{a: 1}"); "root")]
+    #[test_case("{80: http}", "80", Some("http"); "non string keys")]
+    #[test_case("{a: 1}", "a.b", None; "missing")]
+    fn test_get_path(current: &str, path: &str, expected: Option<&str>) {
+        let current = yamllib::YamlLoader::load_from_str(current)
+            .unwrap()
+            .remove(0);
+        let expected = expected.map(|expected| {
+            yamllib::YamlLoader::load_from_str(expected)
+                .unwrap()
+                .remove(0)
+        });
+        assert_eq!(
+            path.parse::<ValuePath>().unwrap().get(&current),
+            expected.as_ref()
+        );
+    }
+
+    #[test_case("{a: {b: 1, c: 2}}", "a.b", "{a: {c: 2}}")]
+    #[test_case("{a: [1, 2, 3]}", "a[0]", "{a: [2, 3]}")]
+    #[test_case("{a: 1}", "b.c", "{a: 1}"; "missing")]
+    fn test_remove_path(current: &str, path: &str, expected: &str) {
+        let mut current = yamllib::YamlLoader::load_from_str(current)
+            .unwrap()
+            .remove(0);
+        path.parse::<ValuePath>().unwrap().remove(&mut current);
+        assert_eq!(
+            current,
+            yamllib::YamlLoader::load_from_str(expected).unwrap()[0]
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use yaml_rust as yamllib;
+
+use crate::documents::{Document, DocumentType};
+use crate::merging::ArrayMergeBehavior;
+
+/// Removes every `null` found in the mappings of `overlay`, along with the
+/// matching keys in `base`. This is how Helm lets a values file (or `--set`)
+/// delete a key that a previous file defined.
+fn prune_nulls(base: Option<&mut yamllib::Yaml>, overlay: &mut yamllib::Yaml) {
+    if let yamllib::Yaml::Hash(overlay_hash) = overlay {
+        let mut base_hash = match base {
+            Some(yamllib::Yaml::Hash(base_hash)) => Some(base_hash),
+            _ => None,
+        };
+
+        let null_keys: Vec<yamllib::Yaml> = overlay_hash
+            .iter()
+            .filter(|(_, value)| matches!(value, yamllib::Yaml::Null))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in null_keys {
+            overlay_hash.remove(&key);
+            if let Some(base_hash) = base_hash.as_mut() {
+                base_hash.remove(&key);
+            }
+        }
+
+        for (key, value) in overlay_hash.iter_mut() {
+            prune_nulls(
+                base_hash
+                    .as_mut()
+                    .and_then(|base_hash| base_hash.get_mut(key)),
+                value,
+            );
+        }
+    }
+}
+
+pub(crate) fn merge(destination: Document, document: Document) -> Document {
+    let doc_type = destination.doc_type();
+    let mut base = destination.into_yaml();
+    let mut overlay = document.into_yaml();
+
+    for (position, overlay_value) in overlay.iter_mut().enumerate() {
+        prune_nulls(base.get_mut(position), overlay_value);
+    }
+
+    Document::from_yaml(doc_type, base).deep_merge(
+        Document::from_yaml(DocumentType::Yaml, overlay),
+        ArrayMergeBehavior::Replace,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn load(source: &str) -> yamllib::Yaml {
+        yamllib::YamlLoader::load_from_str(source)
+            .unwrap()
+            .remove(0)
+    }
+
+    #[test_case("{a: 1, b: 2}", "{b: ~}", "{a: 1}"; "null deletes")]
+    #[test_case("{a: {b: 1, c: 2}}", "{a: {c: ~, d: 3}}", "{a: {b: 1, d: 3}}"; "nested delete")]
+    #[test_case("{a: [1, 2]}", "{a: [3]}", "{a: [3]}"; "arrays replace")]
+    #[test_case("{a: 1}", "{b: {c: ~, d: 1}}", "{a: 1, b: {d: 1}}"; "new keys are pruned")]
+    fn test_helm_merge(current: &str, next: &str, expected: &str) {
+        let merged = merge(
+            Document::Yaml(vec![load(current)]),
+            Document::Yaml(vec![load(next)]),
+        );
+        match merged {
+            Document::Yaml(docs) => assert_eq!(docs, vec![load(expected)]),
+            other => panic!("Unexpected document {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+mod helm;
+mod openapi;
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use strum_macros::{Display, EnumString, EnumVariantNames};
+
+use crate::documents::Document;
+
+/// Merging modes that mimic how other tools layer their configuration files.
+/// A preset takes over the whole fold, so `--arrays` is ignored when one is
+/// selected.
+#[derive(Debug, Clone, Copy, Display, EnumString, EnumVariantNames)]
+#[strum(serialize_all = "kebab-case")]
+pub(crate) enum Preset {
+    /// Helm's values layering: arrays are replaced and a `null` deletes the
+    /// key from the values merged so far.
+    Helm,
+    /// OpenAPI specs split across files: `paths`, `components` and `tags`
+    /// are combined, conflicting operations are rejected and `$ref`s between
+    /// the fragments become local references.
+    Openapi,
+}
+
+#[derive(Debug)]
+pub(crate) enum PresetError {
+    Conflict { filename: PathBuf, message: String },
+}
+
+impl fmt::Display for PresetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Conflict { filename, message } => write!(f, "{:?}: {}", filename, message),
+        }
+    }
+}
+
+impl Preset {
+    /// Gives the preset a look at every input before the fold starts.
+    pub fn prepare(self, documents: &mut [(PathBuf, Document)]) -> Result<(), PresetError> {
+        match self {
+            Self::Helm => Ok(()),
+            Self::Openapi => openapi::prepare(documents),
+        }
+    }
+
+    pub fn merge(
+        self,
+        destination: Document,
+        document: Document,
+        filename: &Path,
+    ) -> Result<Document, PresetError> {
+        match self {
+            Self::Helm => Ok(helm::merge(destination, document)),
+            Self::Openapi => openapi::merge(destination, document, filename),
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+use std::fs::canonicalize;
+use std::path::{Path, PathBuf};
+
+use yaml_rust as yamllib;
+
+use crate::conversions::YamlValue;
+use crate::documents::Document;
+use crate::merging::{ArrayMergeBehavior, DeepMerge};
+use crate::presets::PresetError;
+
+const METHODS: &[&str] = &[
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+fn key(name: &str) -> yamllib::Yaml {
+    yamllib::Yaml::String(name.into())
+}
+
+/// Collects every operation of a spec as `(path, method, operation)`.
+fn operations(spec: &yamllib::Yaml) -> Vec<(&str, &str, &yamllib::Yaml)> {
+    let mut found = Vec::new();
+    if let Some(paths) = spec["paths"].as_hash() {
+        for (path, item) in paths {
+            for method in METHODS {
+                match (path.as_str(), item[*method].is_badvalue()) {
+                    (Some(path), false) => found.push((path, *method, &item[*method])),
+                    _ => continue,
+                }
+            }
+        }
+    }
+    found
+}
+
+/// Makes sure `overlay` can be merged into `base`: the same path and method
+/// may only be defined twice if both definitions are identical, and new
+/// operations can't reuse an `operationId`.
+fn check_conflicts(
+    base: &yamllib::Yaml,
+    overlay: &yamllib::Yaml,
+    filename: &Path,
+) -> Result<(), PresetError> {
+    let existing = operations(base);
+    let operation_ids: HashMap<&str, (&str, &str)> = existing
+        .iter()
+        .filter_map(|(path, method, operation)| {
+            operation["operationId"]
+                .as_str()
+                .map(|id| (id, (*path, *method)))
+        })
+        .collect();
+
+    for (path, method, operation) in operations(overlay) {
+        match existing
+            .iter()
+            .find(|(other_path, other_method, _)| *other_path == path && *other_method == method)
+        {
+            Some((_, _, other)) if *other == operation => continue,
+            Some(_) => {
+                return Err(PresetError::Conflict {
+                    filename: filename.into(),
+                    message: format!(
+                        "`{} {}` is already defined differently",
+                        method.to_uppercase(),
+                        path
+                    ),
+                })
+            }
+            None => {}
+        }
+
+        if let Some(id) = operation["operationId"].as_str() {
+            if let Some((other_path, other_method)) = operation_ids.get(id) {
+                return Err(PresetError::Conflict {
+                    filename: filename.into(),
+                    message: format!(
+                        "operationId `{}` of `{} {}` is already used by `{} {}`",
+                        id,
+                        method.to_uppercase(),
+                        path,
+                        other_method.to_uppercase(),
+                        other_path
+                    ),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes sure no `operationId` is used twice within a single spec, which
+/// `check_conflicts` can't see since it only compares two specs.
+fn check_operation_ids(spec: &yamllib::Yaml, filename: &Path) -> Result<(), PresetError> {
+    let mut operation_ids: HashMap<&str, (&str, &str)> = HashMap::new();
+    for (path, method, operation) in operations(spec) {
+        if let Some(id) = operation["operationId"].as_str() {
+            if let Some((other_path, other_method)) = operation_ids.insert(id, (path, method)) {
+                return Err(PresetError::Conflict {
+                    filename: filename.into(),
+                    message: format!(
+                        "operationId `{}` of `{} {}` is already used by `{} {}`",
+                        id,
+                        method.to_uppercase(),
+                        path,
+                        other_method.to_uppercase(),
+                        other_path
+                    ),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Concatenates the `tags` of both specs, merging tags that share a `name`.
+fn merge_tags(
+    base: Option<yamllib::Yaml>,
+    overlay: Option<yamllib::Yaml>,
+) -> Option<yamllib::Yaml> {
+    let mut tags: Vec<yamllib::Yaml> = Vec::new();
+    for tag in base.into_iter().chain(overlay).flat_map(|tags| match tags {
+        yamllib::Yaml::Array(tags) => tags,
+        other => vec![other],
+    }) {
+        let existing = tags
+            .iter_mut()
+            .find(|other| !tag["name"].is_badvalue() && other["name"] == tag["name"]);
+        match existing {
+            Some(other) => {
+                let merged = YamlValue(other.clone())
+                    .deep_merge(YamlValue(tag), ArrayMergeBehavior::Replace)
+                    .0;
+                *other = merged;
+            }
+            None => tags.push(tag),
+        }
+    }
+
+    if tags.is_empty() {
+        None
+    } else {
+        Some(yamllib::Yaml::Array(tags))
+    }
+}
+
+fn merge_spec(
+    base: yamllib::Yaml,
+    overlay: yamllib::Yaml,
+    filename: &Path,
+) -> Result<yamllib::Yaml, PresetError> {
+    check_conflicts(&base, &overlay, filename)?;
+
+    match (base, overlay) {
+        (yamllib::Yaml::Hash(mut base_hash), yamllib::Yaml::Hash(mut overlay_hash)) => {
+            let tags = merge_tags(
+                base_hash.remove(&key("tags")),
+                overlay_hash.remove(&key("tags")),
+            );
+            let merged = YamlValue(yamllib::Yaml::Hash(base_hash))
+                .deep_merge(
+                    YamlValue(yamllib::Yaml::Hash(overlay_hash)),
+                    ArrayMergeBehavior::Replace,
+                )
+                .0;
+            Ok(match (merged, tags) {
+                (yamllib::Yaml::Hash(mut merged_hash), Some(tags)) => {
+                    merged_hash.insert(key("tags"), tags);
+                    yamllib::Yaml::Hash(merged_hash)
+                }
+                (merged, _) => merged,
+            })
+        }
+        (base, overlay) => Ok(YamlValue(base)
+            .deep_merge(YamlValue(overlay), ArrayMergeBehavior::Replace)
+            .0),
+    }
+}
+
+pub(crate) fn merge(
+    destination: Document,
+    document: Document,
+    filename: &Path,
+) -> Result<Document, PresetError> {
+    let doc_type = destination.doc_type();
+    let mut base = destination.into_yaml().into_iter();
+    let mut overlay = document.into_yaml().into_iter();
+    let mut merged = Vec::new();
+
+    loop {
+        merged.push(match (base.next(), overlay.next()) {
+            (Some(base), Some(overlay)) => merge_spec(base, overlay, filename)?,
+            (Some(base), None) => base,
+            (None, Some(overlay)) => overlay,
+            (None, None) => break,
+        });
+    }
+
+    Ok(Document::from_yaml(doc_type, merged))
+}
+
+/// Turns `$ref`s that point into one of the merged fragments (like
+/// `pets.yaml#/components/schemas/Pet`) into local references, since after
+/// the merge the target lives in the same document. References to a whole
+/// fragment are kept, the merged document has no equivalent for them.
+fn rewrite_refs(value: &mut yamllib::Yaml, directory: &Path, fragments: &[PathBuf]) {
+    match value {
+        yamllib::Yaml::Hash(hash) => {
+            for (entry_key, entry_value) in hash.iter_mut() {
+                match (entry_key.as_str(), &entry_value) {
+                    (Some("$ref"), yamllib::Yaml::String(reference)) => {
+                        let mut parts = reference.splitn(2, '#');
+                        let (file, pointer) = match (parts.next(), parts.next()) {
+                            (Some(file), Some(pointer))
+                                if !file.is_empty() && !pointer.is_empty() =>
+                            {
+                                (file, pointer)
+                            }
+                            _ => continue,
+                        };
+                        let points_to_fragment = canonicalize(directory.join(file))
+                            .map(|target| fragments.contains(&target))
+                            .unwrap_or(false);
+                        if points_to_fragment {
+                            *entry_value = yamllib::Yaml::String(format!("#{}", pointer));
+                        }
+                    }
+                    _ => rewrite_refs(entry_value, directory, fragments),
+                }
+            }
+        }
+        yamllib::Yaml::Array(values) => values
+            .iter_mut()
+            .for_each(|value| rewrite_refs(value, directory, fragments)),
+        _ => {}
+    }
+}
+
+pub(crate) fn prepare(documents: &mut [(PathBuf, Document)]) -> Result<(), PresetError> {
+    let fragments: Vec<PathBuf> = documents
+        .iter()
+        .filter_map(|(filename, _)| canonicalize(filename).ok())
+        .collect();
+
+    for (filename, document) in documents.iter_mut() {
+        let directory = filename
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .to_owned();
+        let doc_type = document.doc_type();
+        let mut yaml = std::mem::replace(document, doc_type.default_document()).into_yaml();
+        for value in yaml.iter_mut() {
+            check_operation_ids(value, filename)?;
+            rewrite_refs(value, &directory, &fragments);
+        }
+        *document = Document::from_yaml(doc_type, yaml);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn load(source: &str) -> yamllib::Yaml {
+        yamllib::YamlLoader::load_from_str(source)
+            .unwrap()
+            .remove(0)
+    }
+
+    #[test_case(
+        "{paths: {/pets: {get: {operationId: listPets}}}, tags: [{name: pets}]}",
+        "{paths: {/pets: {post: {operationId: addPet}}}, tags: [{name: pets, description: Pets}, {name: store}]}",
+        "{paths: {/pets: {get: {operationId: listPets}, post: {operationId: addPet}}}, tags: [{name: pets, description: Pets}, {name: store}]}";
+        "paths and tags"
+    )]
+    #[test_case(
+        "{components: {schemas: {Pet: {type: object}}}}",
+        "{components: {schemas: {Error: {type: object}}, responses: {NotFound: {}}}}",
+        "{components: {schemas: {Pet: {type: object}, Error: {type: object}}, responses: {NotFound: {}}}}";
+        "components"
+    )]
+    #[test_case(
+        "{paths: {/pets: {get: {operationId: listPets}}}}",
+        "{paths: {/pets: {get: {operationId: listPets}}}}",
+        "{paths: {/pets: {get: {operationId: listPets}}}}";
+        "identical operations"
+    )]
+    fn test_openapi_merge(current: &str, next: &str, expected: &str) {
+        assert_eq!(
+            merge_spec(load(current), load(next), Path::new("next.yaml")).unwrap(),
+            load(expected)
+        );
+    }
+
+    #[test_case(
+        "{paths: {/pets: {get: {operationId: listPets}}}}",
+        "{paths: {/pets: {get: {operationId: getPets}}}}";
+        "conflicting operation"
+    )]
+    #[test_case(
+        "{paths: {/pets: {get: {operationId: listPets}}}}",
+        "{paths: {/cats: {get: {operationId: listPets}}}}";
+        "duplicated operationId"
+    )]
+    fn test_openapi_conflicts(current: &str, next: &str) {
+        assert!(merge_spec(load(current), load(next), Path::new("next.yaml")).is_err());
+    }
+
+    #[test_case("{paths: {/pets: {get: {operationId: pets}, post: {operationId: pets}}}}"; "same path")]
+    #[test_case("{paths: {/pets: {get: {operationId: pets}}, /cats: {get: {operationId: pets}}}}"; "other path")]
+    fn test_openapi_duplicated_operation_ids(spec: &str) {
+        assert!(check_operation_ids(&load(spec), Path::new("spec.yaml")).is_err());
+    }
+
+    #[test_case("Cargo.toml#/components/schemas/Pet", "#/components/schemas/Pet"; "fragment")]
+    #[test_case("Cargo.toml", "Cargo.toml"; "whole fragment")]
+    #[test_case("Cargo.toml#", "Cargo.toml#"; "empty pointer")]
+    #[test_case("other.yaml#/a", "other.yaml#/a"; "not a fragment")]
+    #[test_case("#/a", "#/a"; "local")]
+    fn test_rewrite_refs(reference: &str, expected: &str) {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let fragments = vec![canonicalize(directory.join("Cargo.toml")).unwrap()];
+        let mut value = load(&format!("{{schema: {{$ref: '{}'}}}}", reference));
+        rewrite_refs(&mut value, directory, &fragments);
+        assert_eq!(
+            value,
+            load(&format!("{{schema: {{$ref: '{}'}}}}", expected))
+        );
+    }
+}
@@ -0,0 +1,548 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use yaml_rust as yamllib;
+use yamllib::parser::{Event, MarkedEventReceiver, Parser};
+use yamllib::scanner::Marker;
+
+use json as jsonlib;
+
+use crate::commands::ReportFormat;
+use crate::conversions::{inline_value, JsonValue, YamlValue};
+use crate::merging::ArrayMergeBehavior;
+use crate::paths::{key_string, ValuePath};
+
+/// Line numbers (1-based) of every node of every document in a stream.
+pub(crate) type LineMap = Vec<HashMap<ValuePath, usize>>;
+
+enum Frame {
+    Mapping { key: Option<String> },
+    Sequence { next: usize },
+}
+
+#[derive(Default)]
+struct LineCollector {
+    lines: LineMap,
+    frames: Vec<Frame>,
+    paths: Vec<ValuePath>,
+}
+
+impl LineCollector {
+    /// Works out where the node that just started lives. Returns `None` for
+    /// mapping keys, which aren't nodes of their own.
+    fn node_path(&mut self, event: &Event) -> Option<ValuePath> {
+        let parent = self.paths.last().cloned().unwrap_or_default();
+        match self.frames.last_mut() {
+            None => Some(parent),
+            Some(Frame::Sequence { next }) => {
+                *next += 1;
+                Some(parent.index(*next - 1))
+            }
+            Some(Frame::Mapping { key }) => match key.take() {
+                Some(key) => Some(parent.child(key)),
+                None => {
+                    *key = Some(match event {
+                        Event::Scalar(value, ..) => key_string(&yamllib::Yaml::from_str(value)),
+                        // Complex keys can't be part of a path anyway
+                        _ => "?".into(),
+                    });
+                    None
+                }
+            },
+        }
+    }
+}
+
+impl MarkedEventReceiver for LineCollector {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::DocumentStart => self.lines.push(HashMap::new()),
+            Event::Scalar(..) | Event::Alias(_) => {
+                if let Some(path) = self.node_path(&event) {
+                    if let Some(lines) = self.lines.last_mut() {
+                        lines.entry(path).or_insert(mark.line());
+                    }
+                }
+            }
+            Event::MappingStart(_) | Event::SequenceStart(_) => {
+                let path = self.node_path(&event);
+                if let (Some(path), Some(lines)) = (&path, self.lines.last_mut()) {
+                    lines.entry(path.clone()).or_insert(mark.line());
+                }
+                // Containers used as keys still need a frame so their
+                // contents are skipped properly.
+                self.paths
+                    .push(path.unwrap_or_else(|| ValuePath::default().child("?")));
+                self.frames.push(match event {
+                    Event::MappingStart(_) => Frame::Mapping { key: None },
+                    _ => Frame::Sequence { next: 0 },
+                });
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.paths.pop();
+                self.frames.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Finds out the line where every node of `source` (YAML or JSON) starts.
+pub(crate) fn line_map(source: &str) -> LineMap {
+    let mut collector = LineCollector::default();
+    let mut parser = Parser::new(source.chars());
+    match parser.load(&mut collector, true) {
+        Ok(()) => collector.lines,
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Moves every node of a single document stream under `prefix`, like
+/// `Document::nested` does with the document itself.
+pub(crate) fn prefix_lines(lines: LineMap, prefix: &ValuePath) -> LineMap {
+    match lines.len() {
+        1 => lines
+            .into_iter()
+            .map(|lines| {
+                lines
+                    .into_iter()
+                    .map(|(path, line)| {
+                        let mut prefixed = prefix.clone();
+                        prefixed.0.extend(path.0);
+                        (prefixed, line)
+                    })
+                    .collect()
+            })
+            .collect(),
+        // Streams are wrapped as an array, which we don't bother tracking
+        _ => Vec::new(),
+    }
+}
+
+/// Where a value came from.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Origin {
+    pub source: String,
+    pub document: usize,
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)?;
+        if self.document > 0 {
+            write!(f, "#{}", self.document)?;
+        }
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps track of the origin of every leaf while documents are folded, by
+/// replaying the decisions `DeepMerge` makes.
+pub(crate) struct Provenance {
+    null_deletes: bool,
+    origins: Vec<BTreeMap<ValuePath, Origin>>,
+    /// For every node that replaced an earlier value, where that value came
+    /// from.
+    overrides: Vec<BTreeMap<ValuePath, Origin>>,
+}
+
+/// Everything needed to describe where the values of one input come from.
+struct Input<'a> {
+    source: &'a str,
+    document: usize,
+    lines: Option<&'a HashMap<ValuePath, usize>>,
+    array_merge: ArrayMergeBehavior,
+}
+
+impl<'a> Input<'a> {
+    fn origin(&self, path: &ValuePath) -> Origin {
+        Origin {
+            source: self.source.into(),
+            document: self.document,
+            line: self.lines.and_then(|lines| lines.get(path)).copied(),
+        }
+    }
+}
+
+impl Provenance {
+    pub fn new(null_deletes: bool) -> Self {
+        Self {
+            null_deletes,
+            origins: Vec::new(),
+            overrides: Vec::new(),
+        }
+    }
+
+    fn clear(&mut self, document: usize, path: &ValuePath) {
+        if let Some(origins) = self.origins.get_mut(document) {
+            origins.retain(|key, _| !key.0.starts_with(&path.0));
+        }
+        if let Some(overrides) = self.overrides.get_mut(document) {
+            overrides.retain(|key, _| !key.0.starts_with(&path.0));
+        }
+    }
+
+    /// Replaces whatever is at `path` with `value`, remembering where the
+    /// replaced value came from.
+    fn replace(
+        &mut self,
+        path: &ValuePath,
+        source_path: &ValuePath,
+        value: &yamllib::Yaml,
+        input: &Input,
+    ) {
+        let previous = self.origins.get(input.document).and_then(|origins| {
+            origins.get(path).cloned().or_else(|| {
+                origins
+                    .range(path.clone()..)
+                    .next()
+                    .filter(|(key, _)| key.0.starts_with(&path.0))
+                    .map(|(_, origin)| origin.clone())
+            })
+        });
+        self.clear(input.document, path);
+        if !(self.null_deletes && matches!(value, yamllib::Yaml::Null)) {
+            self.assign(path, source_path, value, input);
+        }
+        if let Some(previous) = previous {
+            while self.overrides.len() <= input.document {
+                self.overrides.push(BTreeMap::new());
+            }
+            self.overrides[input.document].insert(path.clone(), previous);
+        }
+    }
+
+    /// An empty container is a leaf of its own, until something is merged
+    /// into it.
+    fn forget_leaf(&mut self, document: usize, path: &ValuePath, still_empty: bool) {
+        if let (false, Some(origins)) = (still_empty, self.origins.get_mut(document)) {
+            origins.remove(path);
+        }
+    }
+
+    /// Marks every leaf of `value` (stored at `path`) as coming from `input`.
+    /// `source_path` is where the value lives in the input itself.
+    fn assign(
+        &mut self,
+        path: &ValuePath,
+        source_path: &ValuePath,
+        value: &yamllib::Yaml,
+        input: &Input,
+    ) {
+        match value {
+            yamllib::Yaml::Hash(hash) if !hash.is_empty() => {
+                for (key, child) in hash {
+                    if !(self.null_deletes && matches!(child, yamllib::Yaml::Null)) {
+                        self.assign(
+                            &path.child_key(key),
+                            &source_path.child_key(key),
+                            child,
+                            input,
+                        );
+                    }
+                }
+            }
+            yamllib::Yaml::Array(values) if !values.is_empty() => {
+                for (index, child) in values.iter().enumerate() {
+                    self.assign(&path.index(index), &source_path.index(index), child, input);
+                }
+            }
+            _ => {
+                while self.origins.len() <= input.document {
+                    self.origins.push(BTreeMap::new());
+                }
+                self.origins[input.document].insert(path.clone(), input.origin(source_path));
+            }
+        }
+    }
+
+    fn replay(
+        &mut self,
+        path: &ValuePath,
+        source_path: &ValuePath,
+        current: Option<&yamllib::Yaml>,
+        overlay: &yamllib::Yaml,
+        input: &Input,
+    ) {
+        match (current, overlay) {
+            (Some(yamllib::Yaml::Hash(current_hash)), yamllib::Yaml::Hash(overlay_hash)) => {
+                self.forget_leaf(input.document, path, overlay_hash.is_empty());
+                for (key, value) in overlay_hash {
+                    let child_path = path.child_key(key);
+                    let existing = current_hash
+                        .iter()
+                        .find(|(current_key, _)| key_string(current_key) == key_string(key))
+                        .map(|(_, existing)| existing);
+                    if self.null_deletes && matches!(value, yamllib::Yaml::Null) {
+                        self.clear(input.document, &child_path);
+                    } else if existing.is_some() {
+                        self.replay(
+                            &child_path,
+                            &source_path.child_key(key),
+                            existing,
+                            value,
+                            input,
+                        );
+                    } else {
+                        self.clear(input.document, &child_path);
+                        self.assign(&child_path, &source_path.child_key(key), value, input);
+                    }
+                }
+            }
+            (Some(yamllib::Yaml::Array(current_values)), yamllib::Yaml::Array(overlay_values))
+                if input.array_merge == ArrayMergeBehavior::Concat =>
+            {
+                self.forget_leaf(input.document, path, overlay_values.is_empty());
+                for (index, value) in overlay_values.iter().enumerate() {
+                    self.assign(
+                        &path.index(current_values.len() + index),
+                        &source_path.index(index),
+                        value,
+                        input,
+                    );
+                }
+            }
+            _ => self.replace(path, source_path, overlay, input),
+        }
+    }
+
+    /// Records what merging `document` (read from `source`) onto
+    /// `destination` with `array_merge` does.
+    pub fn record(
+        &mut self,
+        destination: &[yamllib::Yaml],
+        document: &[yamllib::Yaml],
+        source: &str,
+        lines: &LineMap,
+        array_merge: ArrayMergeBehavior,
+    ) {
+        for (position, overlay) in document.iter().enumerate() {
+            let input = Input {
+                source,
+                document: position,
+                lines: lines.get(position),
+                array_merge,
+            };
+            self.replay(
+                &ValuePath::default(),
+                &ValuePath::default(),
+                destination.get(position),
+                overlay,
+                &input,
+            );
+        }
+    }
+
+    /// Records a value set directly at `path` (like with `--set`).
+    pub fn record_value(&mut self, path: &ValuePath, value: &yamllib::Yaml, source: &str) {
+        let input = Input {
+            source,
+            document: 0,
+            lines: None,
+            array_merge: ArrayMergeBehavior::Replace,
+        };
+        if self.null_deletes && matches!(value, yamllib::Yaml::Null) {
+            self.clear(0, path);
+        } else {
+            self.replace(path, path, value, &input);
+        }
+    }
+
+    /// Records the removal of whatever was at `path` (like with `--unset`).
+    pub fn record_removal(&mut self, path: &ValuePath) {
+        self.clear(0, path);
+    }
+
+    /// Where the leaf at `path` came from.
+    pub fn origin(&self, document: usize, path: &ValuePath) -> Option<&Origin> {
+        self.origins
+            .get(document)
+            .and_then(|origins| origins.get(path))
+    }
+
+    /// Where the value that the node at `path` replaced came from, if it
+    /// replaced anything.
+    pub fn overridden(&self, document: usize, path: &ValuePath) -> Option<&Origin> {
+        self.overrides
+            .get(document)
+            .and_then(|overrides| overrides.get(path))
+    }
+
+    /// Lists every leaf of `result` along with its origin, in document order.
+    pub fn explain<'a>(
+        &'a self,
+        result: &'a [yamllib::Yaml],
+    ) -> Vec<(usize, ValuePath, &'a yamllib::Yaml, Option<&'a Origin>)> {
+        fn leaves<'v>(
+            path: ValuePath,
+            value: &'v yamllib::Yaml,
+            found: &mut Vec<(ValuePath, &'v yamllib::Yaml)>,
+        ) {
+            match value {
+                yamllib::Yaml::Hash(hash) if !hash.is_empty() => hash
+                    .iter()
+                    .for_each(|(key, child)| leaves(path.child_key(key), child, found)),
+                yamllib::Yaml::Array(values) if !values.is_empty() => values
+                    .iter()
+                    .enumerate()
+                    .for_each(|(index, child)| leaves(path.index(index), child, found)),
+                _ => found.push((path, value)),
+            }
+        }
+
+        let mut explained = Vec::new();
+        for (position, document) in result.iter().enumerate() {
+            let mut found = Vec::new();
+            leaves(ValuePath::default(), document, &mut found);
+            for (path, value) in found {
+                let origin = self.origin(position, &path);
+                explained.push((position, path, value, origin));
+            }
+        }
+        explained
+    }
+}
+
+/// Prints the result of `Provenance::explain` as an aligned table of path,
+/// value and origin, or as a JSON array.
+pub(crate) fn render_explanation(
+    explained: &[(usize, ValuePath, &yamllib::Yaml, Option<&Origin>)],
+    format: ReportFormat,
+) -> String {
+    match format {
+        ReportFormat::Text => {
+            let multiple_documents = explained.iter().any(|(document, ..)| *document > 0);
+            let rows: Vec<(String, String, String)> = explained
+                .iter()
+                .map(|(document, path, value, origin)| {
+                    let mut path = path.describe();
+                    if multiple_documents {
+                        path = format!("#{} {}", document, path);
+                    }
+                    let origin = origin
+                        .map(Origin::to_string)
+                        .unwrap_or_else(|| "(unknown)".into());
+                    (path, inline_value(value), origin)
+                })
+                .collect();
+
+            let path_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0);
+            let value_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0);
+            rows.into_iter()
+                .map(|(path, value, origin)| {
+                    format!(
+                        "{:path_width$}  {:value_width$}  {}\n",
+                        path,
+                        value,
+                        origin,
+                        path_width = path_width,
+                        value_width = value_width
+                    )
+                })
+                .collect()
+        }
+        ReportFormat::Json => {
+            let rows = explained
+                .iter()
+                .map(|(document, path, value, origin)| {
+                    let mut object = jsonlib::object::Object::new();
+                    object.insert("document", (*document).into());
+                    object.insert("path", path.to_string().into());
+                    object.insert("value", JsonValue::from(YamlValue((*value).clone())).0);
+                    match origin {
+                        Some(origin) => {
+                            object.insert("source", origin.source.clone().into());
+                            object.insert("source_document", origin.document.into());
+                            object.insert("line", origin.line.into());
+                        }
+                        None => object.insert("source", jsonlib::JsonValue::Null),
+                    }
+                    jsonlib::JsonValue::Object(object)
+                })
+                .collect();
+            format!("{}\n", jsonlib::JsonValue::Array(rows).pretty(2))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn load(source: &str) -> Vec<yamllib::Yaml> {
+        yamllib::YamlLoader::load_from_str(source).unwrap()
+    }
+
+    #[test_case("a: 1\nb:\n  c: 2\n  d: [3, 4]\n", &[("a", 1), ("b.c", 3), ("b.d[1]", 4)])]
+    #[test_case("{\n  \"a\": {\n    \"b\": 1\n  }\n}\n", &[("a", 2), ("a.b", 3)]; "json")]
+    #[test_case("- a: 1\n- b:\n    - 2\n", &[("[0].a", 1), ("[1].b[0]", 3)]; "sequences")]
+    fn test_line_map(source: &str, expected: &[(&str, usize)]) {
+        let lines = line_map(source);
+        for (path, line) in expected {
+            assert_eq!(lines[0].get(&path.parse().unwrap()), Some(line), "{}", path);
+        }
+    }
+
+    #[test_case(
+        ArrayMergeBehavior::Replace,
+        &[("a", "base"), ("b.c", "base"), ("b.d", "prod"), ("l[0]", "prod")]
+    )]
+    #[test_case(
+        ArrayMergeBehavior::Concat,
+        &[("a", "base"), ("b.c", "base"), ("b.d", "prod"), ("l[0]", "base"), ("l[1]", "prod")]
+    )]
+    fn test_record(array_merge: ArrayMergeBehavior, expected: &[(&str, &str)]) {
+        let base = load("{a: 1, b: {c: 2, d: 3}, l: [1]}");
+        let prod = load("{b: {d: 4}, l: [2]}");
+        let result = load(match array_merge {
+            ArrayMergeBehavior::Replace => "{a: 1, b: {c: 2, d: 4}, l: [2]}",
+            ArrayMergeBehavior::Concat => "{a: 1, b: {c: 2, d: 4}, l: [1, 2]}",
+        });
+
+        let mut provenance = Provenance::new(false);
+        provenance.record(&[], &base, "base", &Vec::new(), array_merge);
+        provenance.record(&base, &prod, "prod", &Vec::new(), array_merge);
+
+        let explained: Vec<(String, String)> = provenance
+            .explain(&result)
+            .into_iter()
+            .map(|(_, path, _, origin)| (path.to_string(), origin.unwrap().source.clone()))
+            .collect();
+        let expected: Vec<(String, String)> = expected
+            .iter()
+            .map(|(path, source)| (path.to_string(), source.to_string()))
+            .collect();
+        assert_eq!(explained, expected);
+    }
+
+    #[test]
+    fn test_overridden() {
+        let base = load("{a: 1, b: {c: 2}, l: [1]}");
+        let prod = load("{a: 2, b: {d: 3}, l: [2]}");
+
+        let mut provenance = Provenance::new(false);
+        provenance.record(&[], &base, "base", &Vec::new(), ArrayMergeBehavior::Replace);
+        provenance.record(
+            &base,
+            &prod,
+            "prod",
+            &Vec::new(),
+            ArrayMergeBehavior::Replace,
+        );
+
+        let overridden = |path: &str| {
+            provenance
+                .overridden(0, &path.parse().unwrap())
+                .map(|origin| origin.source.clone())
+        };
+        assert_eq!(overridden("a"), Some("base".into()));
+        assert_eq!(overridden("l"), Some("base".into()));
+        assert_eq!(overridden("b.d"), None);
+        assert_eq!(overridden("b.c"), None);
+    }
+}
@@ -0,0 +1,146 @@
+use yaml_rust as yamllib;
+
+use crate::conversions::inline_value;
+use crate::diff::{diff, Change};
+
+/// What each input did to the accumulated document while folding.
+#[derive(Debug, Default)]
+pub(crate) struct MergeReport {
+    inputs: Vec<(String, Vec<Change>)>,
+}
+
+impl MergeReport {
+    /// Records the changes `source` made, turning `before` into `after`.
+    pub fn record(&mut self, source: &str, before: &[yamllib::Yaml], after: &[yamllib::Yaml]) {
+        // Documents that didn't exist yet count as empty mappings, so their
+        // keys are listed one by one instead of as a single root value.
+        let mut before = before.to_vec();
+        for document in after.iter().skip(before.len()) {
+            before.push(match document {
+                yamllib::Yaml::Hash(_) => yamllib::Yaml::Hash(yamllib::yaml::Hash::new()),
+                _ => yamllib::Yaml::BadValue,
+            });
+        }
+        let changes = diff(&before, after)
+            .into_iter()
+            .map(|change| match change {
+                Change::Changed {
+                    document,
+                    path,
+                    old: yamllib::Yaml::BadValue,
+                    new,
+                } => Change::Added {
+                    document,
+                    path,
+                    value: new,
+                },
+                change => change,
+            })
+            .collect();
+        self.inputs.push((source.into(), changes));
+    }
+
+    /// One section per input, listing the keys it added, the ones it
+    /// overrode (with their old and new values) and the ones it deleted.
+    pub fn render(&self) -> String {
+        let multiple_documents = self
+            .inputs
+            .iter()
+            .flat_map(|(_, changes)| changes)
+            .any(|change| change.document() > 0);
+        let describe = |change: &Change| {
+            if multiple_documents {
+                format!("#{} {}", change.document(), change.path().describe())
+            } else {
+                change.path().describe()
+            }
+        };
+
+        let mut rendered = String::new();
+        for (source, changes) in &self.inputs {
+            rendered.push_str(&format!("{}:\n", source));
+            if changes.is_empty() {
+                rendered.push_str("  (no changes)\n");
+                continue;
+            }
+
+            let mut section = |title: &str, lines: Vec<String>| {
+                if !lines.is_empty() {
+                    rendered.push_str(&format!("  {}:\n", title));
+                    for line in lines {
+                        rendered.push_str(&format!("    {}\n", line));
+                    }
+                }
+            };
+            section(
+                "added",
+                changes
+                    .iter()
+                    .filter_map(|change| match change {
+                        Change::Added { value, .. } => {
+                            Some(format!("{}: {}", describe(change), inline_value(value)))
+                        }
+                        _ => None,
+                    })
+                    .collect(),
+            );
+            section(
+                "overridden",
+                changes
+                    .iter()
+                    .filter_map(|change| match change {
+                        Change::Changed { old, new, .. } => Some(format!(
+                            "{}: {} -> {}",
+                            describe(change),
+                            inline_value(old),
+                            inline_value(new)
+                        )),
+                        _ => None,
+                    })
+                    .collect(),
+            );
+            section(
+                "deleted",
+                changes
+                    .iter()
+                    .filter_map(|change| match change {
+                        Change::Removed { value, .. } => Some(format!(
+                            "{} (was {})",
+                            describe(change),
+                            inline_value(value)
+                        )),
+                        _ => None,
+                    })
+                    .collect(),
+            );
+        }
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(source: &str) -> Vec<yamllib::Yaml> {
+        yamllib::YamlLoader::load_from_str(source).unwrap()
+    }
+
+    #[test]
+    fn test_render() {
+        let base = load("{a: 1, b: {c: 2}}");
+        let merged = load("{a: 2, b: {}, d: [x]}");
+
+        let mut report = MergeReport::default();
+        report.record("base.yaml", &[], &base);
+        report.record("prod.yaml", &base, &merged);
+        report.record("empty.yaml", &merged, &merged);
+
+        assert_eq!(
+            report.render(),
+            "base.yaml:\n  added:\n    a: 1\n    b: {\"c\":2}\n\
+             prod.yaml:\n  added:\n    d: [\"x\"]\n  overridden:\n    a: 1 -> 2\n  deleted:\n    b.c (was 2)\n\
+             empty.yaml:\n  (no changes)\n"
+        );
+    }
+}
@@ -0,0 +1,455 @@
+use std::error::Error;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use strum::VariantNames;
+use strum_macros::{Display, EnumString, EnumVariantNames};
+use yaml_rust as yamllib;
+
+use crate::assignments::Typing;
+use crate::documents::{Document, DocumentType};
+use crate::paths::ValuePath;
+
+/// Where the document of an input is read from.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) enum Source {
+    /// The file at the input's path (or stdin, for `-`).
+    #[default]
+    File,
+    /// The environment variables whose names start with a prefix, written
+    /// as `env:PREFIX`.
+    Environment(Environment),
+    /// A document given on the command line, written as
+    /// `inline:[FORMAT:]TEXT` (YAML by default).
+    Inline(String),
+    /// The file at the input's path as it is in a revision of its git
+    /// repository, written as `git:REV:PATH`.
+    Git { rev: String },
+    /// The output of a command, written as `cmd:COMMAND LINE`.
+    Command(ExternalCommand),
+    /// A file inside the tar or zip archive at the input's path, written as
+    /// `ARCHIVE#ENTRY`. The files of a whole archive are read while it's
+    /// expanded, in a single pass, and carry their `contents`.
+    Archive {
+        entry: PathBuf,
+        contents: Option<String>,
+    },
+}
+
+/// How the names of environment variables are turned into keys.
+#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString, EnumVariantNames)]
+#[strum(serialize_all = "kebab-case")]
+pub(crate) enum KeyCase {
+    Lower,
+    Upper,
+    Preserve,
+}
+
+/// An `env:PREFIX` source: `PREFIX_DB__HOST=x` becomes `{db: {host: x}}`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Environment {
+    pub prefix: String,
+    /// Splits the rest of a name into nested keys.
+    pub separator: String,
+    pub case: KeyCase,
+    pub typing: Typing,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self {
+            prefix: String::new(),
+            separator: "__".into(),
+            case: KeyCase::Lower,
+            typing: Typing::Yaml,
+        }
+    }
+}
+
+impl Environment {
+    /// Handles the qualifiers only environment inputs have: `separator`,
+    /// `case` and `types`. Returns `false` for any other `key`.
+    pub fn qualify(&mut self, key: &str, value: &str) -> Result<bool, String> {
+        match key {
+            "separator" if value.is_empty() => {
+                return Err("The separator of an `env:` input can't be empty".into())
+            }
+            "separator" => self.separator = value.into(),
+            "case" => {
+                self.case = KeyCase::from_str(value).map_err(|_| {
+                    format!(
+                        "Unknown case {:?}, expected one of {}",
+                        value,
+                        KeyCase::VARIANTS.join(", ")
+                    )
+                })?
+            }
+            "types" => {
+                self.typing = Typing::from_str(value).map_err(|_| {
+                    format!(
+                        "Unknown types {:?}, expected one of {}",
+                        value,
+                        Typing::VARIANTS.join(", ")
+                    )
+                })?
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// The path a variable named `name` goes to, if it has the prefix and
+    /// no empty key.
+    fn path(&self, name: &str) -> Option<ValuePath> {
+        let rest = name.strip_prefix(&self.prefix)?;
+        let mut path = ValuePath::default();
+        for key in rest.split(self.separator.as_str()) {
+            if key.is_empty() {
+                return None;
+            }
+            path = path.child(match self.case {
+                KeyCase::Lower => key.to_lowercase(),
+                KeyCase::Upper => key.to_uppercase(),
+                KeyCase::Preserve => key.to_owned(),
+            });
+        }
+        Some(path)
+    }
+
+    fn load_from<I>(&self, variables: I) -> Document
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        // Sorted, so the result doesn't depend on the order of the
+        // environment (and `A` comes before `A__B`)
+        let mut variables: Vec<_> = variables.into_iter().collect();
+        variables.sort();
+
+        let mut document = yamllib::Yaml::Hash(yamllib::yaml::Hash::new());
+        for (name, value) in variables {
+            if let Some(path) = self.path(&name) {
+                path.set(&mut document, self.typing.value(&value));
+            }
+        }
+        Document::from_yaml(DocumentType::Yaml, vec![document])
+    }
+
+    /// Reads the current environment. Variables that aren't valid unicode
+    /// are left out.
+    pub fn load(&self) -> Document {
+        self.load_from(std::env::vars_os().filter_map(|(name, value)| {
+            Some((name.into_string().ok()?, value.into_string().ok()?))
+        }))
+    }
+}
+
+/// Splits a command line into its arguments, the way a shell would without
+/// expanding anything: words are separated by whitespace, single quotes
+/// keep everything as is and double quotes allow `\"` and `\\` escapes.
+fn split_command_line(line: &str) -> Result<Vec<String>, String> {
+    let mut arguments = Vec::new();
+    let mut argument = String::new();
+    // Whether `argument` holds a word, even an empty one like `''`
+    let mut pending = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if pending {
+                    arguments.push(std::mem::take(&mut argument));
+                    pending = false;
+                }
+            }
+            '\'' => {
+                pending = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => argument.push(c),
+                        None => return Err(format!("Unclosed `'` in {:?}", line)),
+                    }
+                }
+            }
+            '"' => {
+                pending = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(escaped @ ('"' | '\\')) => argument.push(escaped),
+                            Some(c) => {
+                                argument.push('\\');
+                                argument.push(c);
+                            }
+                            None => return Err(format!("Unclosed `\"` in {:?}", line)),
+                        },
+                        Some(c) => argument.push(c),
+                        None => return Err(format!("Unclosed `\"` in {:?}", line)),
+                    }
+                }
+            }
+            '\\' => {
+                pending = true;
+                argument.extend(chars.next());
+            }
+            c => {
+                pending = true;
+                argument.push(c);
+            }
+        }
+    }
+    if pending {
+        arguments.push(argument);
+    }
+
+    if arguments.is_empty() {
+        return Err("Empty command".into());
+    }
+    Ok(arguments)
+}
+
+/// A `cmd:COMMAND LINE` source. The command is run without a shell.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ExternalCommand {
+    pub arguments: Vec<String>,
+    pub timeout: Option<Duration>,
+}
+
+impl FromStr for ExternalCommand {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        Ok(Self {
+            arguments: split_command_line(line)?,
+            timeout: None,
+        })
+    }
+}
+
+impl ExternalCommand {
+    /// Handles the qualifier only command inputs have: `timeout`, in
+    /// seconds. Returns `false` for any other `key`.
+    pub fn qualify(&mut self, key: &str, value: &str) -> Result<bool, String> {
+        match key {
+            "timeout" => {
+                let timeout = value
+                    .trim_end_matches('s')
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|seconds| *seconds > 0.0)
+                    .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                    .ok_or_else(|| format!("Invalid timeout {:?}, expected seconds", value))?;
+                self.timeout = Some(timeout);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Runs the command and returns what it printed to stdout. It fails
+    /// when it can't be started, exits with an error or runs for longer than
+    /// the timeout (in which case it's killed).
+    pub fn run(&self) -> Result<String, Box<dyn Error>> {
+        let mut child = Command::new(&self.arguments[0])
+            .args(&self.arguments[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Both pipes are drained while waiting, so a chatty command can't
+        // block on a full pipe
+        let drain = |pipe: Option<Box<dyn Read + Send>>| {
+            thread::spawn(move || {
+                let mut buffer = Vec::new();
+                if let Some(mut pipe) = pipe {
+                    pipe.read_to_end(&mut buffer).map(|_| buffer)
+                } else {
+                    Ok(buffer)
+                }
+            })
+        };
+        let stdout = drain(child.stdout.take().map(|pipe| Box::new(pipe) as _));
+        let stderr = drain(child.stderr.take().map(|pipe| Box::new(pipe) as _));
+
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            match self.timeout {
+                Some(timeout) if started.elapsed() >= timeout => {
+                    child.kill()?;
+                    child.wait()?;
+                    return Err(format!("timed out after {:?}", timeout).into());
+                }
+                _ => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+
+        let stdout = stdout.join().map_err(|_| "couldn't read stdout")??;
+        let stderr = stderr.join().map_err(|_| "couldn't read stderr")??;
+        if !status.success() {
+            let stderr = String::from_utf8_lossy(&stderr);
+            return Err(match stderr.trim() {
+                "" => status.to_string(),
+                stderr => format!("{}: {}", status, stderr),
+            }
+            .into());
+        }
+        Ok(String::from_utf8(stdout)?)
+    }
+}
+
+/// Guesses the format of a command's output: JSON when it looks like an
+/// object or an array, YAML otherwise.
+pub(crate) fn sniff(text: &str) -> DocumentType {
+    match text.trim_start().chars().next() {
+        Some('{') | Some('[') => DocumentType::Json,
+        _ => DocumentType::Yaml,
+    }
+}
+
+/// The contents of `path` in the revision `rev` of the git repository it's
+/// in, from the object database (the working tree is left alone).
+pub(crate) fn git_show(rev: &str, path: &Path) -> Result<String, Box<dyn Error>> {
+    // git would take it for an option
+    if rev.starts_with('-') {
+        return Err(format!("Invalid revision {:?}", rev).into());
+    }
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let filename = path
+        .file_name()
+        .ok_or_else(|| format!("{:?} isn't a file", path))?;
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args(["cat-file", "blob"])
+        .arg(format!("{}:./{}", rev, filename.to_string_lossy()))
+        .output()?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().into());
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("terraform output -json", &["terraform", "output", "-json"]; "plain")]
+    #[test_case(r#"jq -n '{"a": 1}'"#, &["jq", "-n", r#"{"a": 1}"#]; "single quotes")]
+    #[test_case(r#"echo "a \"b\" \n" c\ d ''"#, &["echo", r#"a "b" \n"#, "c d", ""]; "double quotes")]
+    fn test_split_command_line(line: &str, expected: &[&str]) {
+        assert_eq!(split_command_line(line).unwrap(), expected);
+    }
+
+    #[test_case("echo 'a"; "unclosed single quote")]
+    #[test_case(r#"echo "a"#; "unclosed double quote")]
+    #[test_case("  "; "empty")]
+    fn test_split_invalid_command_line(line: &str) {
+        assert!(split_command_line(line).is_err());
+    }
+
+    #[test_case("printf '{\"a\": 1}'", None, Ok("{\"a\": 1}"); "output")]
+    #[test_case("sh -c 'echo oops >&2; exit 3'", None, Err("exit status: 3: oops"); "failure")]
+    #[test_case("false", None, Err("exit status: 1"); "silent failure")]
+    #[test_case("sleep 5", Some(0.1), Err("timed out after 100ms"); "timeout")]
+    fn test_run_command(line: &str, timeout: Option<f64>, expected: Result<&str, &str>) {
+        let command = ExternalCommand {
+            timeout: timeout.map(Duration::from_secs_f64),
+            ..line.parse().unwrap()
+        };
+        assert_eq!(
+            command.run().map_err(|error| error.to_string()),
+            expected.map(String::from).map_err(String::from)
+        );
+    }
+
+    #[test]
+    fn test_git_show() {
+        let root = std::env::temp_dir().join(format!("mungye-git-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("conf")).unwrap();
+        let git = |arguments: &[&str]| {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(&root)
+                .args([
+                    "-c",
+                    "user.name=mungye",
+                    "-c",
+                    "user.email=mungye@localhost",
+                ])
+                .args(["-c", "commit.gpgsign=false"])
+                .args(arguments)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {:?} failed", arguments);
+        };
+        git(&["init", "--quiet"]);
+        std::fs::write(root.join("conf/a.yaml"), "a: 1\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "--quiet", "-m", "first"]);
+        std::fs::write(root.join("conf/a.yaml"), "a: 2\n").unwrap();
+
+        let committed = git_show("HEAD", &root.join("conf/a.yaml"));
+        let missing = git_show("HEAD", &root.join("conf/b.yaml"));
+        let directory = git_show("HEAD", &root.join("conf"));
+        let option = git_show("--output=x", &root.join("conf/a.yaml"));
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(committed.unwrap(), "a: 1\n");
+        assert!(missing.is_err());
+        assert!(directory.is_err());
+        assert_eq!(
+            option.unwrap_err().to_string(),
+            "Invalid revision \"--output=x\""
+        );
+    }
+
+    #[test_case(
+        Environment::default(),
+        "{db: {host: x, port: 5432}, debug: true, log_level: debug}";
+        "defaults"
+    )]
+    #[test_case(
+        Environment { case: KeyCase::Preserve, typing: Typing::String, ..Environment::default() },
+        "{DB: {HOST: x, PORT: '5432'}, DEBUG: 'true', LOG_LEVEL: debug}";
+        "case and types"
+    )]
+    #[test_case(
+        Environment { separator: "_".into(), ..Environment::default() },
+        "{debug: true, log: {level: debug}}";
+        "empty keys are skipped"
+    )]
+    fn test_load_environment(mut environment: Environment, expected: &str) {
+        environment.prefix = "APP_".into();
+        let variables = vec![
+            ("APP_DB__PORT", "5432"),
+            ("APP_DB__HOST", "x"),
+            ("APP_DEBUG", "true"),
+            ("APP_LOG_LEVEL", "debug"),
+            ("APP_", "empty"),
+            ("OTHER_DEBUG", "false"),
+        ];
+        let loaded = environment.load_from(
+            variables
+                .into_iter()
+                .map(|(name, value)| (name.to_owned(), value.to_owned())),
+        );
+        assert_eq!(
+            loaded.into_yaml(),
+            yamllib::YamlLoader::load_from_str(expected).unwrap()
+        );
+    }
+}